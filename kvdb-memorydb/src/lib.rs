@@ -6,19 +6,46 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use kvdb::{DBOp, DBTransaction, DBValue, KeyValueDB};
+use kvdb::{
+	DBOp, DBSnapshot, DBTransaction, DBValue, Error, KeyValueDB, MergeFn, MergeOperator, OptimisticTransaction, Result,
+};
 use parity_util_mem::MallocSizeOf;
 use parking_lot::RwLock;
 use std::{
 	collections::{BTreeMap, HashMap},
-	io,
+	ops::Bound,
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+// Columns are shared with snapshots and copied on write.
+type Column = Arc<BTreeMap<Vec<u8>, DBValue>>;
+// Expiries of the values of a column with a time to live, in milliseconds since the Unix epoch.
+type Expiries = Arc<BTreeMap<Vec<u8>, u64>>;
+
+#[derive(Default, Clone, MallocSizeOf)]
+struct Columns {
+	values: HashMap<u32, Column>,
+	// only for the columns with a time to live
+	expiries: HashMap<u32, Expiries>,
+}
+
 /// A key-value database fulfilling the `KeyValueDB` trait, living in memory.
 /// This is generally intended for tests and is not particularly optimized.
 #[derive(Default, MallocSizeOf)]
 pub struct InMemory {
-	columns: RwLock<HashMap<u32, BTreeMap<Vec<u8>, DBValue>>>,
+	columns: RwLock<Columns>,
+	#[ignore_malloc_size_of = "insignificant"]
+	merge_operators: HashMap<u32, MergeFn>,
+	#[ignore_malloc_size_of = "insignificant"]
+	time_to_live: HashMap<u32, Duration>,
+}
+
+/// A snapshot of an `InMemory` database.
+///
+/// Shares the columns with the database until they are modified by a write.
+pub struct InMemorySnapshot {
+	columns: Columns,
 }
 
 /// Create an in-memory database with the given number of columns.
@@ -27,75 +54,247 @@ pub fn create(num_cols: u32) -> InMemory {
 	let mut cols = HashMap::new();
 
 	for idx in 0..num_cols {
-		cols.insert(idx, BTreeMap::new());
+		cols.insert(idx, Column::default());
 	}
 
-	InMemory { columns: RwLock::new(cols) }
+	InMemory {
+		columns: RwLock::new(Columns { values: cols, expiries: HashMap::new() }),
+		merge_operators: HashMap::new(),
+		time_to_live: HashMap::new(),
+	}
 }
 
-impl KeyValueDB for InMemory {
-	fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
-		let columns = self.columns.read();
-		match columns.get(&col) {
-			None => Err(io::Error::new(io::ErrorKind::Other, format!("No such column family: {:?}", col))),
-			Some(map) => Ok(map.get(key).cloned()),
+fn millis(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+impl Columns {
+	// Get a value by key, unless it has expired.
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		match self.values.get(&col) {
+			None => Err(Error::UnknownColumn(col)),
+			Some(map) => Ok(map.get(key).filter(|_| self.is_live(col)(key)).cloned()),
 		}
 	}
 
-	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
-		let columns = self.columns.read();
-		match columns.get(&col) {
-			None => None,
-			Some(map) => {
-				map.iter().find(|&(ref k, _)| k.starts_with(prefix)).map(|(_, v)| v.to_vec().into_boxed_slice())
-			}
+	// Whether a key of the column has not expired, as of now.
+	fn is_live(&self, col: u32) -> impl Fn(&[u8]) -> bool {
+		let expiries = self.expiries.get(&col).cloned();
+		let now = expiries.as_ref().map_or(0, |_| millis(SystemTime::now()));
+		move |key| expiries.as_ref().and_then(|expiries| expiries.get(key)).filter(|expiry| **expiry <= now).is_none()
+	}
+
+	fn set_expiry(&mut self, col: u32, key: &[u8], expiry: Option<u64>) {
+		if let Some(expiries) = self.expiries.get_mut(&col) {
+			let expiries = Arc::make_mut(expiries);
+			match expiry {
+				Some(expiry) => expiries.insert(key.to_vec(), expiry),
+				None => expiries.remove(key),
+			};
 		}
 	}
 
-	fn write(&self, transaction: DBTransaction) -> io::Result<()> {
-		let mut columns = self.columns.write();
-		let ops = transaction.ops;
+	// Apply the database operations.
+	fn apply(
+		&mut self,
+		merge_operators: &HashMap<u32, MergeFn>,
+		time_to_live: &HashMap<u32, Duration>,
+		ops: Vec<DBOp>,
+	) {
+		let now = if time_to_live.is_empty() { 0 } else { millis(SystemTime::now()) };
 		for op in ops {
 			match op {
 				DBOp::Insert { col, key, value } => {
-					if let Some(col) = columns.get_mut(&col) {
-						col.insert(key.into_vec(), value);
+					if let Some(map) = self.values.get_mut(&col) {
+						Arc::make_mut(map).insert(key.to_vec(), value);
+						let expiry = time_to_live.get(&col).map(|ttl| now.saturating_add(ttl.as_millis() as u64));
+						self.set_expiry(col, &key, expiry);
+					}
+				}
+				DBOp::InsertWithExpiry { col, key, value, expiry } => {
+					if let Some(map) = self.values.get_mut(&col) {
+						Arc::make_mut(map).insert(key.to_vec(), value);
+						self.set_expiry(col, &key, Some(millis(expiry)));
 					}
 				}
 				DBOp::Delete { col, key } => {
-					if let Some(col) = columns.get_mut(&col) {
-						col.remove(&*key);
+					if let Some(map) = self.values.get_mut(&col) {
+						Arc::make_mut(map).remove(&*key);
+						self.set_expiry(col, &key, None);
 					}
 				}
 				DBOp::DeletePrefix { col, prefix } => {
-					if let Some(col) = columns.get_mut(&col) {
-						use std::ops::Bound;
+					if let Some(map) = self.values.get_mut(&col) {
+						let map = Arc::make_mut(map);
 						if prefix.is_empty() {
-							col.clear();
+							map.clear();
 						} else {
 							let start_range = Bound::Included(prefix.to_vec());
 							let keys: Vec<_> = if let Some(end_range) = kvdb::end_prefix(&prefix[..]) {
-								col.range((start_range, Bound::Excluded(end_range))).map(|(k, _)| k.clone()).collect()
+								map.range((start_range, Bound::Excluded(end_range))).map(|(k, _)| k.clone()).collect()
 							} else {
-								col.range((start_range, Bound::Unbounded)).map(|(k, _)| k.clone()).collect()
+								map.range((start_range, Bound::Unbounded)).map(|(k, _)| k.clone()).collect()
 							};
 							for key in keys.into_iter() {
-								col.remove(&key[..]);
+								map.remove(&key[..]);
 							}
 						}
 					}
+					if let Some(expiries) = self.expiries.get_mut(&col) {
+						Arc::make_mut(expiries).retain(|key, _| !key.starts_with(&prefix));
+					}
+				}
+				DBOp::Merge { col, key, operand } => {
+					if let (Some(map), Some(merge)) = (self.values.get_mut(&col), merge_operators.get(&col)) {
+						let map = Arc::make_mut(map);
+						let value = merge(&key, map.get(&*key).map(|v| &v[..]), &[&operand]);
+						map.insert(key.into_vec(), value);
+					}
+				}
+			}
+		}
+	}
+}
+
+impl KeyValueDB for InMemory {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		self.columns.read().get(col, key)
+	}
+
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<Result<Option<DBValue>>> {
+		let columns = self.columns.read();
+		keys.iter().map(|key| columns.get(col, key)).collect()
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		let columns = self.columns.read();
+		match columns.values.get(&col) {
+			None => None,
+			Some(map) => {
+				let is_live = columns.is_live(col);
+				map.iter()
+					.find(|(k, _)| k.starts_with(prefix) && is_live(k))
+					.map(|(_, v)| v.to_vec().into_boxed_slice())
+			}
+		}
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		self.check_transaction(&transaction)?;
+		self.columns.write().apply(&self.merge_operators, &self.time_to_live, transaction.ops);
+		Ok(())
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		self.check_transaction(&transaction.transaction)?;
+		let mut columns = self.columns.write();
+		transaction.validate(|col, key| columns.get(col, key))?;
+		columns.apply(&self.merge_operators, &self.time_to_live, transaction.transaction.ops);
+		Ok(())
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let columns = self.columns.read();
+		match columns.values.get(&col) {
+			Some(map) => {
+				let is_live = columns.is_live(col);
+				Box::new(
+					// TODO: worth optimizing at all?
+					BTreeMap::clone(map)
+						.into_iter()
+						.filter(move |(k, _)| is_live(k))
+						.map(|(k, v)| (k.into_boxed_slice(), v.into_boxed_slice())),
+				)
+			}
+			None => Box::new(None.into_iter()),
+		}
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let columns = self.columns.read();
+		match columns.values.get(&col) {
+			Some(map) => {
+				let is_live = columns.is_live(col);
+				Box::new(
+					BTreeMap::clone(map)
+						.into_iter()
+						.filter(move |(k, _)| k.starts_with(prefix) && is_live(k))
+						.map(|(k, v)| (k.into_boxed_slice(), v.into_boxed_slice())),
+				)
+			}
+			None => Box::new(None.into_iter()),
+		}
+	}
+
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(self.range(col, start, end).into_iter())
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(self.range(col, start, end).into_iter().rev())
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		Box::new(InMemorySnapshot { columns: self.columns.read().clone() })
+	}
+
+	fn restore(&self, _new_db: &str) -> Result<()> {
+		Err(Error::NotSupported("restoring an in-memory database".into()))
+	}
+
+	// Expired values are only hidden from reads until swept here.
+	fn compact_expired(&self) -> Result<()> {
+		let mut columns = self.columns.write();
+		let now = millis(SystemTime::now());
+		let Columns { ref mut values, ref mut expiries } = *columns;
+		for (col, expiries) in expiries.iter_mut() {
+			let expired: Vec<Vec<u8>> =
+				expiries.iter().filter(|(_, expiry)| **expiry <= now).map(|(key, _)| key.clone()).collect();
+			if expired.is_empty() {
+				continue;
+			}
+			let expiries = Arc::make_mut(expiries);
+			let mut map = values.get_mut(col).map(Arc::make_mut);
+			for key in expired {
+				expiries.remove(&key);
+				if let Some(ref mut map) = map {
+					map.remove(&key);
 				}
 			}
 		}
 		Ok(())
 	}
+}
+
+impl DBSnapshot for InMemorySnapshot {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		self.columns.get(col, key)
+	}
 
 	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
-		match self.columns.read().get(&col) {
-			Some(map) => Box::new(
-				// TODO: worth optimizing at all?
-				map.clone().into_iter().map(|(k, v)| (k.into_boxed_slice(), v.into_boxed_slice())),
-			),
+		match self.columns.values.get(&col) {
+			Some(map) => {
+				let is_live = self.columns.is_live(col);
+				Box::new(
+					map.iter()
+						.filter(move |(k, _)| is_live(k))
+						.map(|(k, v)| (k.clone().into_boxed_slice(), v.clone().into_boxed_slice())),
+				)
+			}
 			None => Box::new(None.into_iter()),
 		}
 	}
@@ -105,27 +304,89 @@ impl KeyValueDB for InMemory {
 		col: u32,
 		prefix: &'a [u8],
 	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
-		match self.columns.read().get(&col) {
-			Some(map) => Box::new(
-				map.clone()
-					.into_iter()
-					.filter(move |&(ref k, _)| k.starts_with(prefix))
-					.map(|(k, v)| (k.into_boxed_slice(), v.into_boxed_slice())),
-			),
+		match self.columns.values.get(&col) {
+			Some(map) => {
+				let is_live = self.columns.is_live(col);
+				Box::new(
+					map.range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+						.take_while(move |(k, _)| k.starts_with(prefix))
+						.filter(move |(k, _)| is_live(k))
+						.map(|(k, v)| (k.clone().into_boxed_slice(), v.clone().into_boxed_slice())),
+				)
+			}
 			None => Box::new(None.into_iter()),
 		}
 	}
+}
+
+impl InMemory {
+	/// Set the merge operator of the given column, applied eagerly on every `DBOp::Merge`.
+	pub fn with_merge_operator<M: MergeOperator>(mut self, col: u32) -> InMemory {
+		self.merge_operators.insert(col, M::merge);
+		self
+	}
 
-	fn restore(&self, _new_db: &str) -> io::Result<()> {
-		Err(io::Error::new(io::ErrorKind::Other, "Attempted to restore in-memory database"))
+	/// Set the time to live of the values of the given column, after which they are hidden
+	/// from reads. Expired values are only dropped by `compact_expired`.
+	///
+	/// Values of the column written before have no expiry.
+	pub fn with_time_to_live(mut self, col: u32, ttl: Duration) -> InMemory {
+		self.time_to_live.insert(col, ttl);
+		self.columns.get_mut().expiries.entry(col).or_default();
+		self
+	}
+
+	// Fails if the transaction merges into a column without a merge operator or with a time
+	// to live, or inserts a value with an expiry into a column without a time to live,
+	// so that such transactions are rejected before applying any of their changes.
+	fn check_transaction(&self, transaction: &DBTransaction) -> Result<()> {
+		for op in &transaction.ops {
+			match *op {
+				DBOp::Merge { col, .. } if self.time_to_live.contains_key(&col) => {
+					return Err(Error::NotSupported(format!("merge into column {} with a time to live", col)))
+				}
+				DBOp::Merge { col, .. } if !self.merge_operators.contains_key(&col) => {
+					return Err(Error::NotSupported(format!("no merge operator for column {}", col)))
+				}
+				DBOp::InsertWithExpiry { col, .. } if !self.time_to_live.contains_key(&col) => {
+					return Err(Error::NotSupported(format!("no time to live for column {}", col)))
+				}
+				_ => {}
+			}
+		}
+		Ok(())
+	}
+
+	// Copies out the key/value pairs of the given column within the given bounds.
+	fn range(&self, col: u32, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<(Box<[u8]>, Box<[u8]>)> {
+		// `BTreeMap::range` panics on inverted ranges.
+		let is_empty = match (start, end) {
+			(Bound::Included(s), Bound::Included(e)) => s > e,
+			(Bound::Included(s), Bound::Excluded(e))
+			| (Bound::Excluded(s), Bound::Included(e))
+			| (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+			_ => false,
+		};
+		let columns = self.columns.read();
+		match columns.values.get(&col) {
+			Some(map) if !is_empty => {
+				let is_live = columns.is_live(col);
+				map.range::<[u8], _>((start, end))
+					.filter(|(k, _)| is_live(k))
+					.map(|(k, v)| (k.clone().into_boxed_slice(), v.clone().into_boxed_slice()))
+					.collect()
+			}
+			_ => Vec::new(),
+		}
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::create;
+	use kvdb::KeyValueDB;
 	use kvdb_shared_tests as st;
-	use std::io;
+	use std::{io, time::Duration};
 
 	#[test]
 	fn get_fails_with_non_existing_column() -> io::Result<()> {
@@ -139,6 +400,12 @@ mod tests {
 		st::test_put_and_get(&db)
 	}
 
+	#[test]
+	fn get_many() -> io::Result<()> {
+		let db = create(1);
+		st::test_get_many(&db)
+	}
+
 	#[test]
 	fn delete_and_get() -> io::Result<()> {
 		let db = create(1);
@@ -163,6 +430,128 @@ mod tests {
 		st::test_iter_with_prefix(&db)
 	}
 
+	#[test]
+	fn iter_range() -> io::Result<()> {
+		let db = create(1);
+		st::test_iter_range(&db)
+	}
+
+	#[test]
+	fn iter_range_rev() -> io::Result<()> {
+		let db = create(1);
+		st::test_iter_range_rev(&db)
+	}
+
+	#[test]
+	fn snapshot() -> io::Result<()> {
+		let db = create(1);
+		st::test_snapshot(&db)
+	}
+
+	#[test]
+	fn optimistic_transaction() -> io::Result<()> {
+		let db = create(1);
+		st::test_optimistic_transaction(&db)
+	}
+
+	#[test]
+	fn merge() -> io::Result<()> {
+		let db = create(1).with_merge_operator::<st::AppendOperator>(0);
+		st::test_merge(&db)
+	}
+
+	#[test]
+	fn expiry() -> io::Result<()> {
+		let db = create(2).with_time_to_live(0, Duration::from_secs(3600));
+		st::test_expiry(&db)?;
+
+		let db = create(1).with_time_to_live(0, Duration::from_millis(1));
+		let mut batch = db.transaction();
+		batch.put(0, b"key1", b"horse");
+		db.write(batch)?;
+		let snapshot = db.snapshot();
+		std::thread::sleep(Duration::from_millis(10));
+		assert!(db.get(0, b"key1")?.is_none());
+		assert!(snapshot.get(0, b"key1")?.is_none());
+		assert_eq!(db.columns.read().values[&0].len(), 1);
+		db.compact_expired()?;
+		assert!(db.columns.read().values[&0].is_empty());
+		assert!(db.columns.read().expiries[&0].is_empty());
+
+		let mut batch = db.transaction();
+		batch.merge(0, b"key1", b"operand");
+		assert!(matches!(db.write(batch), Err(kvdb::Error::NotSupported(_))));
+		Ok(())
+	}
+
+	#[test]
+	fn merge_fails_without_merge_operator() {
+		let db = create(2).with_merge_operator::<st::AppendOperator>(0);
+		let mut batch = db.transaction();
+		batch.put(1, b"key", b"value");
+		batch.merge(1, b"key", b"operand");
+		assert!(matches!(db.write(batch), Err(kvdb::Error::NotSupported(_))));
+		assert!(db.get(1, b"key").unwrap().is_none());
+	}
+
+	#[test]
+	fn subscription() -> io::Result<()> {
+		st::test_wrapper_conformance(|columns| kvdb::SubscribableDB::new(create(columns)))
+	}
+
+	#[test]
+	fn export_import() -> io::Result<()> {
+		let db = create(2);
+		st::test_export_import(&db, &create(2))
+	}
+
+	#[test]
+	fn migrations() -> io::Result<()> {
+		let db = create(3);
+		st::test_migrations(&db)
+	}
+
+	#[test]
+	fn prefixed() -> io::Result<()> {
+		use kvdb::PrefixedDB;
+		use std::sync::Arc;
+
+		st::test_wrapper_conformance(|columns| PrefixedDB::new(Arc::new(create(columns)), b"\x01"))?;
+		st::test_io_stats(&PrefixedDB::new(Arc::new(create(st::IO_STATS_NUM_COLUMNS)), b"\x01"))
+	}
+
+	#[test]
+	fn compression() -> io::Result<()> {
+		use kvdb::{Codec, CompressedDB};
+
+		st::test_wrapper_conformance(|columns| CompressedDB::new(create(columns)).with_codec(0, Codec::Lz4))
+	}
+
+	#[test]
+	fn cache() -> io::Result<()> {
+		use kvdb::CachedDB;
+
+		st::test_wrapper_conformance(|columns| {
+			(0..columns).fold(CachedDB::new(create(columns)), |db, col| db.with_column_budget(col, 1024))
+		})?;
+		st::test_merge(
+			&CachedDB::new(create(1).with_merge_operator::<st::AppendOperator>(0)).with_column_budget(0, 1024),
+		)
+	}
+
+	#[test]
+	fn thread_pool() -> io::Result<()> {
+		use kvdb::{BlockingDB, ThreadPoolDB};
+
+		let pool = futures::executor::ThreadPool::new()?;
+		st::test_wrapper_conformance(|columns| BlockingDB::new(ThreadPoolDB::new(create(columns), pool.clone())))
+	}
+
+	#[test]
+	fn overlay() -> io::Result<()> {
+		st::test_wrapper_conformance(|columns| kvdb::OverlayDB::new(create(columns)))
+	}
+
 	#[test]
 	fn complex() -> io::Result<()> {
 		let db = create(1);
@@ -0,0 +1,134 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains the incremental backups of databases, wrapping the RocksDB `BackupEngine`.
+
+use crate::map_rocksdb_err;
+use rocksdb::backup::{BackupEngineOptions, RestoreOptions};
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+// Directories of the backup directory holding the files shared by the backups.
+const SHARED_DIRS: [&str; 2] = ["shared", "shared_checksum"];
+// Directory the backup to restore is staged in, within the backup directory.
+const STAGING_DIR: &str = "restore.tmp";
+
+/// Information about a backup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackupInfo {
+	/// Identifier of the backup, increasing with each backup.
+	pub id: u32,
+	/// Time the backup was created at, in seconds since the Unix epoch.
+	pub timestamp: i64,
+	/// Size of the files of the backup in bytes, including the files shared with other backups.
+	pub size: u64,
+	/// Number of files of the backup.
+	pub num_files: u32,
+}
+
+/// Incremental backups of a database, stored in a directory.
+///
+/// The files unchanged since the previous backup are shared between the backups.
+pub struct BackupEngine {
+	inner: rocksdb::backup::BackupEngine,
+	path: PathBuf,
+}
+
+impl BackupEngine {
+	/// Open the backups stored in the given directory. Creates it if it does not exist.
+	pub fn open<P: AsRef<Path>>(path: P) -> kvdb::Result<BackupEngine> {
+		let path = path.as_ref();
+		let inner =
+			rocksdb::backup::BackupEngine::open(&BackupEngineOptions::default(), path).map_err(map_rocksdb_err)?;
+		Ok(BackupEngine { inner, path: path.to_path_buf() })
+	}
+
+	pub(crate) fn create(&mut self, db: &rocksdb::DB) -> kvdb::Result<u32> {
+		self.inner.create_new_backup_flush(db, true).map_err(map_rocksdb_err)?;
+		self.list().iter().map(|info| info.id).max().ok_or_else(|| kvdb::Error::Other("backup not found".into()))
+	}
+
+	/// The backups, ordered by identifier.
+	pub fn list(&self) -> Vec<BackupInfo> {
+		let mut backups: Vec<_> = self
+			.inner
+			.get_backup_info()
+			.into_iter()
+			.map(|info| BackupInfo {
+				id: info.backup_id,
+				timestamp: info.timestamp,
+				size: info.size,
+				num_files: info.num_files,
+			})
+			.collect();
+		backups.sort_by_key(|info| info.id);
+		backups
+	}
+
+	/// Check that the files of the backup with the given identifier exist and have the expected sizes.
+	pub fn verify(&self, id: u32) -> kvdb::Result<()> {
+		self.inner.verify_backup(id).map_err(map_rocksdb_err)
+	}
+
+	/// Delete the oldest backups, keeping the given number of the latest ones.
+	pub fn purge(&mut self, keep: usize) -> kvdb::Result<()> {
+		self.inner.purge_old_backups(keep).map_err(map_rocksdb_err)
+	}
+
+	/// Restore the backup with the given identifier to a database at the given path,
+	/// which can then be opened with `Database::open`, or swapped in with `Database::restore`.
+	///
+	/// The path must not be the one of an open database. The backup is staged in the backup
+	/// directory meanwhile, so restores are exclusive like the other changes to the directory.
+	pub fn restore<P: AsRef<Path>>(&mut self, id: u32, path: P) -> kvdb::Result<()> {
+		if !self.list().iter().any(|info| info.id == id) {
+			return Err(kvdb::Error::Other(format!("backup {} not found", id)));
+		}
+		// RocksDB only restores the latest backup, so the backup is staged alone in a backup
+		// directory of its own, hard linking its files.
+		let staging = self.path.join(STAGING_DIR);
+		let _ = fs::remove_dir_all(&staging);
+		let result = stage_backup(&self.path, &staging, id).map_err(kvdb::Error::from).and_then(|_| {
+			let path = path.as_ref();
+			let mut staged = rocksdb::backup::BackupEngine::open(&BackupEngineOptions::default(), &staging)
+				.map_err(map_rocksdb_err)?;
+			staged.restore_from_latest_backup(path, path, &RestoreOptions::default()).map_err(map_rocksdb_err)
+		});
+		// ignore errors
+		let _ = fs::remove_dir_all(&staging);
+		result
+	}
+}
+
+// Stage the backup with the given identifier in its own backup directory.
+fn stage_backup(from: &Path, to: &Path, id: u32) -> io::Result<()> {
+	let id = id.to_string();
+	fs::create_dir_all(to.join("meta"))?;
+	fs::hard_link(from.join("meta").join(&id), to.join("meta").join(&id))?;
+	link_dir(&from.join("private").join(&id), &to.join("private").join(&id))?;
+	for dir in SHARED_DIRS.iter() {
+		if from.join(dir).is_dir() {
+			link_dir(&from.join(dir), &to.join(dir))?;
+		}
+	}
+	Ok(())
+}
+
+// Hard link the files of a directory into another one.
+fn link_dir(from: &Path, to: &Path) -> io::Result<()> {
+	fs::create_dir_all(to)?;
+	for entry in fs::read_dir(from)? {
+		let entry = entry?;
+		if entry.file_type()?.is_file() {
+			fs::hard_link(entry.path(), to.join(entry.file_name()))?;
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,128 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains the building of the SST files bulk loaded into a column,
+//! bypassing the write-ahead log and the memtables.
+
+use crate::{map_rocksdb_err, MB};
+use rocksdb::{Options, SstFileWriter};
+use std::{
+	borrow::Cow,
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// Options of the bulk loading of a column with `Database::bulk_load`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BulkLoadOptions {
+	/// Size in bytes of the SST files, after which the following keys go to a new file.
+	/// 256 MiB by default.
+	pub max_file_size: u64,
+	/// Remove the SST files once ingested, moving them into the database instead of copying them.
+	/// Enabled by default.
+	pub remove_files: bool,
+}
+
+impl Default for BulkLoadOptions {
+	fn default() -> Self {
+		BulkLoadOptions { max_file_size: 256 * MB as u64, remove_files: true }
+	}
+}
+
+/// The SST files built, along with the number of keys and bytes written to them.
+pub struct Files {
+	pub paths: Vec<PathBuf>,
+	pub keys: u64,
+	pub bytes: u64,
+}
+
+impl Files {
+	/// Remove the files, ignoring errors.
+	pub fn remove(&self) {
+		for path in &self.paths {
+			let _ = fs::remove_file(path);
+		}
+	}
+}
+
+/// Build SST files in `dir` from the key/value pairs, which must be sorted by strictly increasing keys.
+///
+/// `encode` is applied to the values before they are written. On error, the files built are removed.
+pub fn build_files<I, K, V>(
+	opts: &Options,
+	dir: &Path,
+	name: &str,
+	pairs: I,
+	options: &BulkLoadOptions,
+	encode: impl Fn(&[u8]) -> Cow<[u8]>,
+) -> kvdb::Result<Files>
+where
+	I: IntoIterator<Item = (K, V)>,
+	K: AsRef<[u8]>,
+	V: AsRef<[u8]>,
+{
+	let mut files = Files { paths: Vec::new(), keys: 0, bytes: 0 };
+	let result = write_files(opts, dir, name, pairs, options, encode, &mut files);
+	if result.is_err() {
+		files.remove();
+	}
+	result.map(|_| files)
+}
+
+fn write_files<I, K, V>(
+	opts: &Options,
+	dir: &Path,
+	name: &str,
+	pairs: I,
+	options: &BulkLoadOptions,
+	encode: impl Fn(&[u8]) -> Cow<[u8]>,
+	files: &mut Files,
+) -> kvdb::Result<()>
+where
+	I: IntoIterator<Item = (K, V)>,
+	K: AsRef<[u8]>,
+	V: AsRef<[u8]>,
+{
+	fs::create_dir_all(dir)?;
+	let mut writer: Option<SstFileWriter> = None;
+	let mut last_key: Option<Vec<u8>> = None;
+	for (key, value) in pairs {
+		let key = key.as_ref();
+		if let Some(ref last_key) = last_key {
+			if key <= &last_key[..] {
+				return Err(kvdb::Error::Other(format!(
+					"bulk loaded keys must be strictly increasing, {:?} follows {:?}",
+					key, last_key
+				)));
+			}
+		}
+		let mut current = match writer.take() {
+			Some(current) if current.file_size() < options.max_file_size => current,
+			finished => {
+				if let Some(mut finished) = finished {
+					finished.finish().map_err(map_rocksdb_err)?;
+				}
+				let path = dir.join(format!("{}-{}.sst", name, files.paths.len()));
+				let current = SstFileWriter::create(opts);
+				files.paths.push(path.clone());
+				current.open(&path).map_err(map_rocksdb_err)?;
+				current
+			}
+		};
+		let value = encode(value.as_ref());
+		current.put(key, &value).map_err(map_rocksdb_err)?;
+		files.keys += 1;
+		files.bytes += (key.len() + value.len()) as u64;
+		last_key = Some(key.to_vec());
+		writer = Some(current);
+	}
+	if let Some(mut writer) = writer {
+		writer.finish().map_err(map_rocksdb_err)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,95 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains the table mapping the column indices to the RocksDB column families.
+//!
+//! The column `i` is stored in the column family `col{i}`, unless columns have been dropped or moved
+//! out of order, in which case the mapping is persisted in a column family of its own. The databases
+//! without this column family keep the default mapping.
+
+use crate::map_rocksdb_err;
+use rocksdb::{Options, WriteOptions, DB};
+
+/// Name of the column family holding the table.
+pub const TABLE_COLUMN_FAMILY: &str = "kvdb-columns";
+const TABLE_KEY: &[u8] = b"columns";
+
+/// The column family names by column index, `None` for the unused indices.
+pub type Table = Vec<Option<String>>;
+
+/// Name of the column family of the column in the default mapping.
+pub fn default_name(col: u32) -> String {
+	format!("col{}", col)
+}
+
+/// The default mapping of the given number of columns.
+pub fn default_table(columns: u32) -> Table {
+	(0..columns).map(|col| Some(default_name(col))).collect()
+}
+
+/// A column family name unused by the table, preferring the default name of the next index.
+pub fn unused_name(table: &Table) -> String {
+	(table.len() as u32..)
+		.chain(0..)
+		.map(default_name)
+		.find(|name| !table.iter().any(|mapped| mapped.as_ref() == Some(name)))
+		.expect("the table has a finite number of names; qed")
+}
+
+/// Whether the column family is used by the database, either by a column or by the table.
+pub fn is_used(table: &Table, name: &str) -> bool {
+	name == TABLE_COLUMN_FAMILY
+		|| name == rocksdb::DEFAULT_COLUMN_FAMILY_NAME
+		|| table.iter().any(|mapped| mapped.as_deref() == Some(name))
+}
+
+/// Read the table of the database at the given path, with the names of its existing column families.
+///
+/// The table is `None` if the database has none, or does not exist yet.
+pub fn read(opts: &Options, path: &str) -> kvdb::Result<(Vec<String>, Option<Table>)> {
+	let existing = match DB::list_cf(opts, path) {
+		Ok(existing) => existing,
+		Err(_) => return Ok((Vec::new(), None)),
+	};
+	if !existing.iter().any(|name| name == TABLE_COLUMN_FAMILY) {
+		return Ok((existing, None));
+	}
+	let db = DB::open_cf_for_read_only(opts, path, [TABLE_COLUMN_FAMILY], false).map_err(map_rocksdb_err)?;
+	let cf = db.cf_handle(TABLE_COLUMN_FAMILY).expect("the table column family was opened above; qed");
+	let table = db.get_cf(cf, TABLE_KEY).map_err(map_rocksdb_err)?;
+	Ok((existing, table.map(|table| decode(&table))))
+}
+
+/// Persist the table, unless it is the default mapping of a database without a table.
+pub fn write(db: &mut DB, table: &Table) -> kvdb::Result<()> {
+	if db.cf_handle(TABLE_COLUMN_FAMILY).is_none() {
+		if *table == default_table(table.len() as u32) {
+			return Ok(());
+		}
+		db.create_cf(TABLE_COLUMN_FAMILY, &Options::default()).map_err(map_rocksdb_err)?;
+	}
+	let cf = db.cf_handle(TABLE_COLUMN_FAMILY).expect("the table column family was created above; qed");
+	let mut write_opts = WriteOptions::default();
+	write_opts.set_sync(true);
+	db.put_cf_opt(cf, TABLE_KEY, encode(table), &write_opts).map_err(map_rocksdb_err)
+}
+
+// One line per index, empty for the unused ones.
+fn encode(table: &Table) -> Vec<u8> {
+	table.iter().map(|name| name.as_deref().unwrap_or("")).collect::<Vec<_>>().join("\n").into_bytes()
+}
+
+fn decode(table: &[u8]) -> Table {
+	if table.is_empty() {
+		return Vec::new();
+	}
+	String::from_utf8_lossy(table)
+		.split('\n')
+		.map(|name| if name.is_empty() { None } else { Some(name.to_owned()) })
+		.collect()
+}
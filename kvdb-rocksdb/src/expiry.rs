@@ -0,0 +1,88 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains the handling of the columns with a time to live.
+//!
+//! The values of these columns are stored prefixed with their expiry, in milliseconds since the
+//! Unix epoch as a little-endian `u64`. Reads hide the expired values and strip the expiry of the
+//! others, while a compaction filter drops the expired values from storage.
+
+use crate::iter::KeyValuePair;
+use kvdb::DBValue;
+use rocksdb::compaction_filter::Decision;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the compaction filter of the columns with a time to live.
+pub const COMPACTION_FILTER_NAME: &str = "kvdb_expiry";
+
+const EXPIRY_LEN: usize = 8;
+
+/// The given time in milliseconds since the Unix epoch.
+pub fn millis(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Prefix the value with its expiry, for storage.
+pub fn encode(expiry: u64, value: &[u8]) -> DBValue {
+	let mut stored = Vec::with_capacity(EXPIRY_LEN + value.len());
+	stored.extend_from_slice(&expiry.to_le_bytes());
+	stored.extend_from_slice(value);
+	stored
+}
+
+// The expiry of a stored value, `None` if it has none.
+fn expiry(stored: &[u8]) -> Option<u64> {
+	if stored.len() < EXPIRY_LEN {
+		return None;
+	}
+	let mut expiry = [0u8; EXPIRY_LEN];
+	expiry.copy_from_slice(&stored[..EXPIRY_LEN]);
+	Some(u64::from_le_bytes(expiry))
+}
+
+/// Compaction filter of the columns with a time to live, dropping the expired values.
+pub fn compaction_filter(_level: u32, _key: &[u8], value: &[u8]) -> Decision {
+	match expiry(value) {
+		Some(expiry) if expiry <= millis(SystemTime::now()) => Decision::Remove,
+		_ => Decision::Keep,
+	}
+}
+
+/// Decodes the values read from a column, as of the time it was created.
+#[derive(Clone, Copy)]
+pub struct Reader {
+	// `None` for the columns without a time to live
+	now: Option<u64>,
+}
+
+impl Reader {
+	pub fn new(has_time_to_live: bool) -> Self {
+		Reader { now: if has_time_to_live { Some(millis(SystemTime::now())) } else { None } }
+	}
+
+	/// The value without its expiry, `None` if it has expired.
+	///
+	/// Values too short to have an expiry are hidden as well, as they were not written with one.
+	pub fn value(self, stored: &[u8]) -> Option<&[u8]> {
+		match self.now {
+			Some(now) => match expiry(stored) {
+				Some(expiry) if expiry > now => Some(&stored[EXPIRY_LEN..]),
+				_ => None,
+			},
+			None => Some(stored),
+		}
+	}
+
+	/// The key/value pair without the expiry of the value, `None` if it has expired.
+	pub fn pair(self, (key, stored): KeyValuePair) -> Option<KeyValuePair> {
+		match self.now {
+			Some(_) => self.value(&stored).map(|value| (key, value.into())),
+			None => Some((key, stored)),
+		}
+	}
+}
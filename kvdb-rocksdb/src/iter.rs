@@ -32,7 +32,7 @@ pub struct ReadGuardedIterator<'a, I, T> {
 // We can't implement `StableAddress` for a `RwLockReadGuard`
 // directly due to orphan rules.
 #[repr(transparent)]
-struct UnsafeStableAddress<'a, T>(RwLockReadGuard<'a, T>);
+pub(crate) struct UnsafeStableAddress<'a, T>(pub(crate) RwLockReadGuard<'a, T>);
 
 impl<'a, T> Deref for UnsafeStableAddress<'a, T> {
 	type Target = T;
@@ -45,7 +45,7 @@ impl<'a, T> Deref for UnsafeStableAddress<'a, T> {
 // RwLockReadGuard dereferences to a stable address; qed
 unsafe impl<'a, T> StableAddress for UnsafeStableAddress<'a, T> {}
 
-struct DerefWrapper<T>(T);
+pub(crate) struct DerefWrapper<T>(pub(crate) T);
 
 impl<T> Deref for DerefWrapper<T> {
 	type Target = T;
@@ -82,6 +82,12 @@ pub trait IterationHandler {
 	/// https://github.com/facebook/rocksdb/blob/master/include/rocksdb/options.h#L1169).
 	/// The `Iterator` iterates over keys which start with the provided `prefix`.
 	fn iter_with_prefix(&self, col: u32, prefix: &[u8], read_opts: ReadOptions) -> Self::Iterator;
+	/// Create an `Iterator` over a `ColumnFamily` corresponding to the passed index. Takes
+	/// `ReadOptions` to allow configuration of the new iterator (see
+	/// https://github.com/facebook/rocksdb/blob/master/include/rocksdb/options.h#L1169).
+	/// The `Iterator` starts from the first key for `Direction::Forward` and from the last key
+	/// for `Direction::Reverse`, so the range is expected to be set via the iterate bounds of `read_opts`.
+	fn iter_range(&self, col: u32, direction: Direction, read_opts: ReadOptions) -> Self::Iterator;
 }
 
 impl<'a, T> ReadGuardedIterator<'a, <&'a T as IterationHandler>::Iterator, T>
@@ -105,6 +111,17 @@ where
 		Self { inner: Self::new_inner(read_lock, |db| db.iter_with_prefix(col, prefix, read_opts)) }
 	}
 
+	/// Creates a new `ReadGuardedIterator` that maps `RwLock<RocksDB>` to `RwLock<DBIterator>`,
+	/// where `DBIterator` iterates over keys within the iterate bounds of `read_opts` in the given direction.
+	pub fn new_with_range(
+		read_lock: RwLockReadGuard<'a, Option<T>>,
+		col: u32,
+		direction: Direction,
+		read_opts: ReadOptions,
+	) -> Self {
+		Self { inner: Self::new_inner(read_lock, |db| db.iter_range(col, direction, read_opts)) }
+	}
+
 	fn new_inner(
 		rlock: RwLockReadGuard<'a, Option<T>>,
 		f: impl FnOnce(&'a T) -> <&'a T as IterationHandler>::Iterator,
@@ -126,4 +143,12 @@ impl<'a> IterationHandler for &'a DBAndColumns {
 	fn iter_with_prefix(&self, col: u32, prefix: &[u8], read_opts: ReadOptions) -> Self::Iterator {
 		self.db.iterator_cf_opt(self.cf(col as usize), read_opts, IteratorMode::From(prefix, Direction::Forward))
 	}
+
+	fn iter_range(&self, col: u32, direction: Direction, read_opts: ReadOptions) -> Self::Iterator {
+		let mode = match direction {
+			Direction::Forward => IteratorMode::Start,
+			Direction::Reverse => IteratorMode::End,
+		};
+		self.db.iterator_cf_opt(self.cf(col as usize), read_opts, mode)
+	}
 }
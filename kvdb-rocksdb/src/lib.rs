@@ -6,21 +6,46 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+mod backup;
+mod bulk;
+mod columns;
+mod expiry;
 mod iter;
+mod properties;
+mod snapshot;
 mod stats;
-
-use std::{cmp, collections::HashMap, convert::identity, error, fs, io, mem, path::Path, result};
+mod verify;
+
+use std::{
+	borrow::Cow,
+	cmp,
+	collections::HashMap,
+	convert::identity,
+	env, fs, mem,
+	ops::Bound,
+	path::Path,
+	result,
+	time::{Duration, SystemTime},
+};
 
 use parity_util_mem::MallocSizeOf;
 use parking_lot::RwLock;
 use rocksdb::{
-	BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, Error, Options, ReadOptions, WriteBatch, WriteOptions, DB,
+	BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, DBCompactionStyle, DBCompressionType, DataBlockIndexType,
+	Direction, Error, FifoCompactOptions, IngestExternalFileOptions, MergeOperands, Options, ReadOptions,
+	SliceTransform, WriteBatch, WriteOptions, DB,
 };
 
 use crate::iter::KeyValuePair;
 use fs_swap::{swap, swap_nonatomic};
-use kvdb::{DBOp, DBTransaction, DBValue, KeyValueDB};
-use log::{debug, warn};
+use kvdb::{DBOp, DBSnapshot, DBTransaction, DBValue, KeyValueDB, MergeOperator, OptimisticTransaction};
+use log::{debug, info, warn};
+
+pub use crate::backup::{BackupEngine, BackupInfo};
+pub use crate::bulk::BulkLoadOptions;
+pub use crate::properties::{ColumnProperties, LiveFile};
+pub use crate::snapshot::Snapshot;
+pub use crate::verify::{BadFile, BadRange, VerifyReport};
 
 #[cfg(target_os = "linux")]
 use regex::Regex;
@@ -31,11 +56,18 @@ use std::path::PathBuf;
 #[cfg(target_os = "linux")]
 use std::process::Command;
 
-fn other_io_err<E>(e: E) -> io::Error
-where
-	E: Into<Box<dyn error::Error + Send + Sync>>,
-{
-	io::Error::new(io::ErrorKind::Other, e)
+// RocksDB errors only carry a status message, so they are classified by its prefix.
+fn map_rocksdb_err(err: Error) -> kvdb::Error {
+	let msg = err.into_string();
+	if msg.starts_with("Corruption:") {
+		kvdb::Error::Corruption(msg)
+	} else if msg.contains("No space left on device") {
+		kvdb::Error::NoSpace(msg)
+	} else if msg.starts_with("Not implemented:") {
+		kvdb::Error::NotSupported(msg)
+	} else {
+		kvdb::Error::Other(msg)
+	}
 }
 
 // Used for memory budget.
@@ -140,10 +172,196 @@ impl CompactionProfile {
 	}
 }
 
+// Signature of the native merge functions.
+type MergeFn = fn(&[u8], Option<&[u8]>, &MergeOperands) -> Option<Vec<u8>>;
+
+/// Merge operator of a column.
+#[derive(Clone, Copy)]
+pub struct ColumnMergeOperator {
+	name: &'static str,
+	full_merge: MergeFn,
+}
+
+impl ColumnMergeOperator {
+	/// Create the RocksDB merge operator for the given `MergeOperator`.
+	pub fn new<M: MergeOperator>() -> Self {
+		ColumnMergeOperator { name: M::NAME, full_merge: full_merge::<M> }
+	}
+}
+
+fn full_merge<M: MergeOperator>(key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+	let operands: Vec<&[u8]> = operands.iter().collect();
+	Some(M::merge(key, existing, &operands))
+}
+
+// Refuses to combine operands without the existing value, leaving them to `full_merge`.
+fn no_partial_merge(_key: &[u8], _existing: Option<&[u8]>, _operands: &MergeOperands) -> Option<Vec<u8>> {
+	None
+}
+
+/// Compression of the data of a column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+	/// No compression.
+	None,
+	/// Snappy compression.
+	Snappy,
+	/// LZ4 compression, requires the `lz4` feature.
+	Lz4,
+	/// Zstandard compression, requires the `zstd` feature.
+	Zstd,
+}
+
+impl Compression {
+	fn to_rocksdb(self) -> DBCompressionType {
+		match self {
+			Compression::None => DBCompressionType::None,
+			Compression::Snappy => DBCompressionType::Snappy,
+			Compression::Lz4 => DBCompressionType::Lz4,
+			Compression::Zstd => DBCompressionType::Zstd,
+		}
+	}
+}
+
+/// Compaction style of a column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionStyle {
+	/// Leveled compaction, suited to most workloads.
+	Level,
+	/// Universal compaction, trading space and read amplification for less write amplification.
+	Universal,
+	/// FIFO compaction, for append-only data such as logs.
+	///
+	/// The oldest files are deleted, along with their values, once the total size of the files
+	/// of the column exceeds `max_table_files_size` bytes.
+	/// RocksDB requires all the files to be kept open, so `max_open_files` is overridden to `-1`.
+	Fifo { max_table_files_size: u64 },
+}
+
+/// Tuning options of a column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColumnConfig {
+	/// Compression of the data of the column.
+	/// Snappy by default.
+	pub compression: Compression,
+	/// Length of the fixed key prefix the column is scanned by.
+	///
+	/// Builds bloom filters of the prefixes, used by `iter_with_prefix` for prefixes at least this long.
+	/// Disabled by default.
+	pub prefix_extractor_len: Option<usize>,
+	/// Bits per key of the bloom filter, `None` to disable the bloom filter.
+	/// 10 by default.
+	pub bloom_bits: Option<i32>,
+	/// Block size in bytes, `None` to use the block size of the compaction profile.
+	/// `None` by default.
+	pub block_size: Option<usize>,
+	/// Compaction style.
+	/// Leveled compaction by default.
+	pub compaction_style: CompactionStyle,
+	/// Optimize the column for point lookups, adding hash indexes to the data blocks
+	/// and a bloom filter to the memtables.
+	/// Disabled by default.
+	pub optimize_for_point_lookup: bool,
+}
+
+impl Default for ColumnConfig {
+	fn default() -> Self {
+		ColumnConfig {
+			compression: Compression::Snappy,
+			prefix_extractor_len: None,
+			bloom_bits: Some(10),
+			block_size: None,
+			compaction_style: CompactionStyle::Level,
+			optimize_for_point_lookup: false,
+		}
+	}
+}
+
+/// Durability of the writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WriteDurability {
+	/// Sync the write-ahead log to disk before the write returns,
+	/// so that the write survives a crash of the machine.
+	pub sync: bool,
+	/// Skip the write-ahead log, losing the write if the process crashes before it is flushed to disk.
+	/// Cannot be combined with `sync`: such writes, and opening a database with such a default
+	/// durability, fail with `Error::NotSupported`.
+	pub disable_wal: bool,
+	/// Fail rather than wait when the write would be delayed or stalled by the pending compactions.
+	pub no_slowdown: bool,
+}
+
+impl WriteDurability {
+	// Fails if RocksDB cannot write with this durability.
+	fn check(self) -> kvdb::Result<()> {
+		if self.sync && self.disable_wal {
+			return Err(kvdb::Error::NotSupported("synced writes skipping the write-ahead log".into()));
+		}
+		Ok(())
+	}
+
+	fn write_options(self) -> WriteOptions {
+		let mut write_opts = WriteOptions::default();
+		write_opts.set_sync(self.sync);
+		write_opts.disable_wal(self.disable_wal);
+		write_opts.set_no_slowdown(self.no_slowdown);
+		write_opts
+	}
+}
+
+/// Limits of the background flushes and compactions, and of the writes when they fall behind.
+///
+/// `None` keeps the default of RocksDB, or the one derived from the memory budget and the compaction style.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IoLimits {
+	/// Rate in bytes per second the flushes and compactions write at, leaving the rest of
+	/// the disk bandwidth to the reads and writes. Unlimited by default.
+	pub rate_limit: Option<i64>,
+	/// Maximum number of concurrent flushes and compactions. Half the number of CPUs by default.
+	pub max_background_jobs: Option<i32>,
+	/// Size in bytes of the memtables of all the columns after which the largest one is flushed.
+	pub db_write_buffer_size: Option<usize>,
+	/// Number of memtables of a column, including the one written to, after which the writes are stopped
+	/// until one is flushed.
+	pub max_write_buffer_number: Option<i32>,
+	/// Number of level 0 files of a column after which the writes are slowed down.
+	pub level0_slowdown_writes_trigger: Option<i32>,
+	/// Number of level 0 files of a column after which the writes are stopped.
+	pub level0_stop_writes_trigger: Option<i32>,
+	/// Estimated number of bytes the compactions of a column have to rewrite after which the writes are slowed down.
+	pub soft_pending_compaction_bytes_limit: Option<usize>,
+	/// Estimated number of bytes the compactions of a column have to rewrite after which the writes are stopped.
+	pub hard_pending_compaction_bytes_limit: Option<usize>,
+}
+
+impl IoLimits {
+	// Apply the limits of the columns.
+	fn apply_to_column(&self, opts: &mut Options) {
+		if let Some(number) = self.max_write_buffer_number {
+			opts.set_max_write_buffer_number(number);
+		}
+		if let Some(trigger) = self.level0_slowdown_writes_trigger {
+			opts.set_level_zero_slowdown_writes_trigger(trigger);
+		}
+		if let Some(trigger) = self.level0_stop_writes_trigger {
+			opts.set_level_zero_stop_writes_trigger(trigger);
+		}
+		if let Some(limit) = self.soft_pending_compaction_bytes_limit {
+			opts.set_soft_pending_compaction_bytes_limit(limit);
+		}
+		if let Some(limit) = self.hard_pending_compaction_bytes_limit {
+			opts.set_hard_pending_compaction_bytes_limit(limit);
+		}
+	}
+}
+
 /// Database configuration
 #[derive(Clone)]
 pub struct DatabaseConfig {
-	/// Max number of open files.
+	/// Max number of open files, `-1` to keep all the files open.
+	///
+	/// Overridden to `-1` for a secondary instance, or when any column uses `CompactionStyle::Fifo`,
+	/// as RocksDB requires all the files to be kept open then.
 	pub max_open_files: i32,
 	/// Memory budget (in MiB) used for setting block cache size and
 	/// write buffer size for each column including the default one.
@@ -177,6 +395,39 @@ pub struct DatabaseConfig {
 	/// if the secondary instance reads and applies state changes before the primary instance compacts them.
 	/// More info: https://github.com/facebook/rocksdb/wiki/Secondary-instance
 	pub secondary: Option<String>,
+	/// Open the database read-only, without locking it or writing to its directory,
+	/// so that it can be opened while another process has it open.
+	/// Ignored for secondary instances, which are read-only as well.
+	/// Disabled by default.
+	///
+	/// Writes, column changes, compactions and flushes fail with `NotSupported`.
+	/// The RocksDB info log is written to the temporary directory of the system instead.
+	pub read_only: bool,
+	/// Repair the database when opening it, if it is corrupted or has been marked as corrupted
+	/// after a read hit a corruption. Ignored for read-only instances.
+	/// Disabled by default: opening such a database fails with `Error::Corruption`,
+	/// and an open database is repaired explicitly with `Database::repair`.
+	///
+	/// The repair salvages as much data as possible, but may lose the keys of the corrupted files.
+	pub repair_on_open: bool,
+	/// Merge operators of the columns, required to merge values with `DBOp::Merge`.
+	///
+	/// Secondary and read-only instances are opened with them as well, so that they read merged values.
+	pub merge_operators: HashMap<u32, ColumnMergeOperator>,
+	/// Time to live of the values of the columns, after which they are hidden from reads
+	/// and dropped by compactions. Values can also be given an expiry with `DBOp::InsertWithExpiry`.
+	///
+	/// Values are stored along with their expiry, so the time to live of a column holding values
+	/// can only be changed, not added or removed. Columns with a time to live do not support merges.
+	pub time_to_live: HashMap<u32, Duration>,
+	/// Tuning options of the columns, including the ones added later with `add_column`.
+	/// If the tuning options of a column are not specified, `ColumnConfig::default()` is used.
+	pub column_config: HashMap<u32, ColumnConfig>,
+	/// Durability of the writes, unless specified with `Database::write_with_durability`.
+	/// Neither synced nor skipping the write-ahead log by default.
+	pub write_durability: WriteDurability,
+	/// Limits of the background flushes and compactions, and of the writes when they fall behind.
+	pub io_limits: IoLimits,
 }
 
 impl DatabaseConfig {
@@ -202,19 +453,72 @@ impl DatabaseConfig {
 		self.memory_budget.get(&col).unwrap_or(&DB_DEFAULT_COLUMN_MEMORY_BUDGET_MB) * MB
 	}
 
+	// Returns the tuning options of the specified column.
+	fn column_config_for_col(&self, col: u32) -> ColumnConfig {
+		self.column_config.get(&col).copied().unwrap_or_default()
+	}
+
 	// Get column family configuration with the given block based options.
-	fn column_config(&self, block_opts: &BlockBasedOptions, col: u32) -> Options {
+	fn column_options(&self, block_opts: &BlockOptions, col: u32) -> Options {
 		let column_mem_budget = self.memory_budget_for_col(col);
+		let column_config = self.column_config_for_col(col);
 		let mut opts = Options::default();
 
-		opts.set_level_compaction_dynamic_level_bytes(true);
-		opts.set_block_based_table_factory(block_opts);
-		opts.optimize_level_style_compaction(column_mem_budget);
+		opts.set_block_based_table_factory(block_opts.column(col));
+		match column_config.compaction_style {
+			CompactionStyle::Level => {
+				opts.set_level_compaction_dynamic_level_bytes(true);
+				opts.optimize_level_style_compaction(column_mem_budget);
+			}
+			CompactionStyle::Universal => opts.optimize_universal_style_compaction(column_mem_budget),
+			CompactionStyle::Fifo { max_table_files_size } => {
+				// sizes the memtables the same way as the leveled compaction
+				opts.optimize_level_style_compaction(column_mem_budget);
+				let mut fifo_opts = FifoCompactOptions::default();
+				fifo_opts.set_max_table_files_size(max_table_files_size);
+				opts.set_compaction_style(DBCompactionStyle::Fifo);
+				opts.set_fifo_compaction_options(&fifo_opts);
+			}
+		}
 		opts.set_target_file_size_base(self.compaction.initial_file_size);
+		opts.set_compression_type(column_config.compression.to_rocksdb());
 		opts.set_compression_per_level(&[]);
+		if let Some(len) = column_config.prefix_extractor_len {
+			opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(len));
+		}
+		if column_config.optimize_for_point_lookup {
+			// the memtable part of RocksDB's `OptimizeForPointLookup`, which also replaces the block cache
+			opts.set_memtable_prefix_bloom_ratio(0.02);
+			opts.set_memtable_whole_key_filtering(true);
+		}
+		if let Some(merge_operator) = self.merge_operators.get(&col) {
+			opts.set_merge_operator(merge_operator.name, merge_operator.full_merge, no_partial_merge);
+		}
+		if self.time_to_live.contains_key(&col) {
+			opts.set_compaction_filter(expiry::COMPACTION_FILTER_NAME, expiry::compaction_filter);
+		}
+		self.io_limits.apply_to_column(&mut opts);
 
 		opts
 	}
+
+	// Get the read options to iterate over the keys of the column with the given prefix.
+	fn prefix_read_options(&self, col: u32, prefix: &[u8]) -> ReadOptions {
+		let mut read_opts = generate_read_options();
+		// rocksdb doesn't work with an empty upper bound
+		if let Some(end_prefix) = kvdb::end_prefix(prefix) {
+			read_opts.set_iterate_upper_bound(end_prefix);
+		}
+		// the prefix bloom filters can only be used for prefixes covering the extracted one
+		match self.column_config_for_col(col).prefix_extractor_len {
+			Some(len) if prefix.len() >= len => {
+				read_opts.set_total_order_seek(false);
+				read_opts.set_prefix_same_as_start(true);
+			}
+			_ => {}
+		}
+		read_opts
+	}
 }
 
 impl Default for DatabaseConfig {
@@ -227,28 +531,35 @@ impl Default for DatabaseConfig {
 			keep_log_file_num: 1,
 			enable_statistics: false,
 			secondary: None,
+			read_only: false,
+			repair_on_open: false,
+			merge_operators: HashMap::new(),
+			time_to_live: HashMap::new(),
+			column_config: HashMap::new(),
+			write_durability: WriteDurability::default(),
+			io_limits: IoLimits::default(),
 		}
 	}
 }
 
 struct DBAndColumns {
 	db: DB,
-	column_names: Vec<String>,
+	column_names: columns::Table,
 }
 
 impl MallocSizeOf for DBAndColumns {
 	fn size_of(&self, ops: &mut parity_util_mem::MallocSizeOfOps) -> usize {
 		let mut total = self.column_names.size_of(ops)
-			// we have at least one column always, so we can call property on it
-			+ self.db
-				.property_int_value_cf(self.cf(0), "rocksdb.block-cache-usage")
-				.unwrap_or(Some(0))
-				.map(|x| x as usize)
-				.unwrap_or(0);
-
-		for v in 0..self.column_names.len() {
-			total += self.static_property_or_warn(v, "rocksdb.estimate-table-readers-mem");
-			total += self.static_property_or_warn(v, "rocksdb.cur-size-all-mem-tables");
+			// the block cache is shared by the columns, and has no usage when it is disabled
+			+ self
+				.columns()
+				.next()
+				.and_then(|col| self.db.property_int_value_cf(self.cf(col), "rocksdb.block-cache-usage").ok().flatten())
+				.unwrap_or(0) as usize;
+
+		for col in self.columns() {
+			total += self.static_property_or_warn(col, "rocksdb.estimate-table-readers-mem");
+			total += self.static_property_or_warn(col, "rocksdb.size-all-mem-tables");
 		}
 
 		total
@@ -257,7 +568,28 @@ impl MallocSizeOf for DBAndColumns {
 
 impl DBAndColumns {
 	fn cf(&self, i: usize) -> &ColumnFamily {
-		self.db.cf_handle(&self.column_names[i]).expect("the specified column name is correct; qed")
+		let name = self.column_names[i].as_ref().expect("the specified column is used; qed");
+		self.db.cf_handle(name).expect("the specified column name is correct; qed")
+	}
+
+	fn has_column(&self, col: u32) -> bool {
+		matches!(self.column_names.get(col as usize), Some(Some(_)))
+	}
+
+	// The indices of the used columns.
+	fn columns(&self) -> impl Iterator<Item = usize> + '_ {
+		self.column_names.iter().enumerate().filter(|(_, name)| name.is_some()).map(|(col, _)| col)
+	}
+
+	// Drop the used column, persisting the new mapping before dropping the column family,
+	// which is otherwise dropped at the next opening.
+	fn drop_column(&mut self, col: usize) -> kvdb::Result<()> {
+		let mut names = self.column_names.clone();
+		let name = names[col].take().expect("the specified column is used; qed");
+		trim_unused(&mut names);
+		columns::write(&mut self.db, &names)?;
+		self.column_names = names;
+		self.db.drop_cf(&name).map_err(map_rocksdb_err)
 	}
 
 	fn static_property_or_warn(&self, col: usize, prop: &str) -> usize {
@@ -271,6 +603,13 @@ impl DBAndColumns {
 	}
 }
 
+// Remove the unused indices following the last used column.
+fn trim_unused(column_names: &mut columns::Table) {
+	while let Some(None) = column_names.last() {
+		column_names.pop();
+	}
+}
+
 /// Key-Value database.
 #[derive(MallocSizeOf)]
 pub struct Database {
@@ -285,21 +624,25 @@ pub struct Database {
 	#[ignore_malloc_size_of = "insignificant"]
 	read_opts: ReadOptions,
 	#[ignore_malloc_size_of = "insignificant"]
-	block_opts: BlockBasedOptions,
+	block_opts: BlockOptions,
 	#[ignore_malloc_size_of = "insignificant"]
 	stats: stats::RunningDbStats,
+	// Plain writes share the lock, optimistic transactions take it exclusively
+	// so that no write can slip in between validating the reads and committing.
+	#[ignore_malloc_size_of = "insignificant"]
+	commit_lock: RwLock<()>,
 }
 
 #[inline]
-fn check_for_corruption<T, P: AsRef<Path>>(path: P, res: result::Result<T, Error>) -> io::Result<T> {
+fn check_for_corruption<T, P: AsRef<Path>>(path: P, res: result::Result<T, Error>) -> kvdb::Result<T> {
 	if let Err(ref s) = res {
 		if is_corrupted(s) {
-			warn!("DB corrupted: {}. Repair will be triggered on next restart", s);
+			warn!("DB corrupted: {}. Marked as corrupted until repaired", s);
 			let _ = fs::File::create(path.as_ref().join(Database::CORRUPTION_FILE_NAME));
 		}
 	}
 
-	res.map_err(other_io_err)
+	res.map_err(map_rocksdb_err)
 }
 
 fn is_corrupted(err: &Error) -> bool {
@@ -317,48 +660,120 @@ fn generate_options(config: &DatabaseConfig) -> Options {
 	}
 	opts.set_use_fsync(false);
 	opts.create_if_missing(true);
-	if config.secondary.is_some() {
+	let fifo =
+		config.column_config.values().any(|column| matches!(column.compaction_style, CompactionStyle::Fifo { .. }));
+	if config.secondary.is_some() || fifo {
 		opts.set_max_open_files(-1)
 	} else {
 		opts.set_max_open_files(config.max_open_files);
 	}
 	opts.set_bytes_per_sync(1 * MB as u64);
 	opts.set_keep_log_file_num(1);
-	opts.increase_parallelism(cmp::max(1, num_cpus::get() as i32 / 2));
+	if config.read_only && config.secondary.is_none() {
+		// RocksDB writes its info log even in read-only mode
+		opts.set_db_log_dir(env::temp_dir());
+	}
+	let background_jobs = config.io_limits.max_background_jobs.unwrap_or(num_cpus::get() as i32 / 2);
+	opts.increase_parallelism(cmp::max(1, background_jobs));
+	if let Some(rate_limit) = config.io_limits.rate_limit {
+		// the refill period and the fairness of the reads recommended by RocksDB
+		opts.set_ratelimiter(rate_limit, 100_000, 10);
+	}
+	if let Some(size) = config.io_limits.db_write_buffer_size {
+		opts.set_db_write_buffer_size(size);
+	}
 
 	opts
 }
 
+/// Converts range bounds into the inclusive lower and the exclusive upper iterate bounds RocksDB expects.
+fn iterate_bounds(start: Bound<&[u8]>, end: Bound<&[u8]>) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+	// The smallest key greater than `key`.
+	let successor = |key: &[u8]| {
+		let mut next = Vec::with_capacity(key.len() + 1);
+		next.extend_from_slice(key);
+		next.push(0);
+		next
+	};
+	let lower = match start {
+		Bound::Included(key) => Some(key.to_vec()),
+		Bound::Excluded(key) => Some(successor(key)),
+		Bound::Unbounded => None,
+	};
+	let upper = match end {
+		Bound::Included(key) => Some(successor(key)),
+		Bound::Excluded(key) => Some(key.to_vec()),
+		Bound::Unbounded => None,
+	};
+	(lower, upper)
+}
+
 fn generate_read_options() -> ReadOptions {
 	let mut read_opts = ReadOptions::default();
 	read_opts.set_verify_checksums(false);
+	// iterate over all the keys of the columns with a prefix extractor, not only the ones sharing the prefix of the first
+	read_opts.set_total_order_seek(true);
 	read_opts
 }
 
+/// Block based options of the columns, sharing the block cache.
+struct BlockOptions {
+	default: BlockBasedOptions,
+	columns: HashMap<u32, BlockBasedOptions>,
+}
+
+impl BlockOptions {
+	// Returns the block based options of the specified column.
+	fn column(&self, col: u32) -> &BlockBasedOptions {
+		self.columns.get(&col).unwrap_or(&self.default)
+	}
+}
+
 /// Generate the block based options for RocksDB, based on the given `DatabaseConfig`.
-fn generate_block_based_options(config: &DatabaseConfig) -> io::Result<BlockBasedOptions> {
-	let mut block_opts = BlockBasedOptions::default();
-	block_opts.set_block_size(config.compaction.block_size);
-	// See https://github.com/facebook/rocksdb/blob/a1523efcdf2f0e8133b9a9f6e170a0dad49f928f/include/rocksdb/table.h#L246-L271 for details on what the format versions are/do.
-	block_opts.set_format_version(5);
-	block_opts.set_block_restart_interval(16);
+///
+/// The options of the columns added later with `add_column` are generated as well, as they share the block cache.
+fn generate_block_based_options(config: &DatabaseConfig) -> kvdb::Result<BlockOptions> {
 	// Set cache size as recommended by
 	// https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#block-cache-size
 	let cache_size = config.memory_budget() / 3;
-	if cache_size == 0 {
-		block_opts.disable_cache()
-	} else {
-		let cache = rocksdb::Cache::new_lru_cache(cache_size).map_err(other_io_err)?;
-		block_opts.set_block_cache(&cache);
-		// "index and filter blocks will be stored in block cache, together with all other data blocks."
-		// See: https://github.com/facebook/rocksdb/wiki/Memory-usage-in-RocksDB#indexes-and-filter-blocks
-		block_opts.set_cache_index_and_filter_blocks(true);
-		// Don't evict L0 filter/index blocks from the cache
-		block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
-	}
-	block_opts.set_bloom_filter(10, true);
-
-	Ok(block_opts)
+	let cache =
+		if cache_size == 0 { None } else { Some(rocksdb::Cache::new_lru_cache(cache_size).map_err(map_rocksdb_err)?) };
+	let column_block_opts = |column_config: &ColumnConfig| {
+		let mut block_opts = BlockBasedOptions::default();
+		block_opts.set_block_size(column_config.block_size.unwrap_or(config.compaction.block_size));
+		// See https://github.com/facebook/rocksdb/blob/a1523efcdf2f0e8133b9a9f6e170a0dad49f928f/include/rocksdb/table.h#L246-L271 for details on what the format versions are/do.
+		block_opts.set_format_version(5);
+		block_opts.set_block_restart_interval(16);
+		match cache {
+			None => block_opts.disable_cache(),
+			Some(ref cache) => {
+				block_opts.set_block_cache(cache);
+				// "index and filter blocks will be stored in block cache, together with all other data blocks."
+				// See: https://github.com/facebook/rocksdb/wiki/Memory-usage-in-RocksDB#indexes-and-filter-blocks
+				block_opts.set_cache_index_and_filter_blocks(true);
+				// Don't evict L0 filter/index blocks from the cache
+				block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+			}
+		}
+		if let Some(bloom_bits) = column_config.bloom_bits {
+			block_opts.set_bloom_filter(f64::from(bloom_bits), true);
+		}
+		if column_config.optimize_for_point_lookup {
+			// the table part of RocksDB's `OptimizeForPointLookup`
+			block_opts.set_data_block_index_type(DataBlockIndexType::BinaryAndHash);
+			block_opts.set_data_block_hash_ratio(0.75);
+		}
+		block_opts
+	};
+
+	Ok(BlockOptions {
+		default: column_block_opts(&ColumnConfig::default()),
+		columns: config
+			.column_config
+			.iter()
+			.map(|(col, column_config)| (*col, column_block_opts(column_config)))
+			.collect(),
+	})
 }
 
 impl Database {
@@ -369,29 +784,64 @@ impl Database {
 	/// # Safety
 	///
 	/// The number of `config.columns` must not be zero.
-	pub fn open(config: &DatabaseConfig, path: &str) -> io::Result<Database> {
+	pub fn open(config: &DatabaseConfig, path: &str) -> kvdb::Result<Database> {
 		assert!(config.columns > 0, "the number of columns must not be zero");
+		config.write_durability.check()?;
 
 		let opts = generate_options(config);
 		let block_opts = generate_block_based_options(config)?;
 
-		// attempt database repair if it has been previously marked as corrupted
+		// repair the database if it has been previously marked as corrupted, and allowed to
 		let db_corrupted = Path::new(path).join(Database::CORRUPTION_FILE_NAME);
-		if db_corrupted.exists() {
+		if db_corrupted.exists() && !config.read_only {
+			if !config.repair_on_open {
+				return Err(kvdb::Error::Corruption(format!("the database at {} is marked as corrupted", path)));
+			}
 			warn!("DB has been previously marked as corrupted, attempting repair");
-			DB::repair(&opts, path).map_err(other_io_err)?;
+			DB::repair(&opts, path).map_err(map_rocksdb_err)?;
 			fs::remove_file(db_corrupted)?;
 		}
+		Self::open_with(config, path, opts, block_opts)
+	}
 
-		let column_names: Vec<_> = (0..config.columns).map(|c| format!("col{}", c)).collect();
-		let write_opts = WriteOptions::default();
+	// Opens the database whether or not it is marked as corrupted.
+	fn open_with(
+		config: &DatabaseConfig,
+		path: &str,
+		opts: Options,
+		block_opts: BlockOptions,
+	) -> kvdb::Result<Database> {
+		let write_opts = config.write_durability.write_options();
 		let read_opts = generate_read_options();
 
-		let db = if let Some(secondary_path) = &config.secondary {
-			Self::open_secondary(&opts, path, secondary_path.as_str(), column_names.as_slice())?
-		} else {
-			let column_names: Vec<&str> = column_names.iter().map(|s| s.as_str()).collect();
-			Self::open_primary(&opts, path, config, column_names.as_slice(), &block_opts)?
+		let (existing, table) = columns::read(&opts, path)?;
+		let (db, column_names) = match table {
+			Some(mut column_names) => {
+				if column_names.len() != config.columns as usize {
+					return Err(kvdb::Error::Other(format!(
+						"the database has {} columns, {} expected",
+						column_names.len(),
+						config.columns
+					)));
+				}
+				let db = Self::open_mapped(&opts, path, config, &mut column_names, &existing, &block_opts)?;
+				(db, column_names)
+			}
+			None => {
+				let column_names: Vec<_> = (0..config.columns).map(columns::default_name).collect();
+				let db = if let Some(secondary_path) = &config.secondary {
+					Self::open_secondary(&opts, path, secondary_path.as_str(), config, &column_names, &block_opts)?
+				} else if config.read_only {
+					let cf_descriptors = column_names.iter().enumerate().map(|(i, name)| {
+						ColumnFamilyDescriptor::new(name, config.column_options(&block_opts, i as u32))
+					});
+					DB::open_cf_descriptors_read_only(&opts, path, cf_descriptors, false).map_err(map_rocksdb_err)?
+				} else {
+					let column_names: Vec<&str> = column_names.iter().map(|s| s.as_str()).collect();
+					Self::open_primary(&opts, path, config, column_names.as_slice(), &block_opts)?
+				};
+				(db, column_names.into_iter().map(Some).collect())
+			}
 		};
 
 		Ok(Database {
@@ -403,6 +853,7 @@ impl Database {
 			write_opts,
 			block_opts,
 			stats: stats::RunningDbStats::new(),
+			commit_lock: RwLock::new(()),
 		})
 	}
 
@@ -412,10 +863,10 @@ impl Database {
 		path: &str,
 		config: &DatabaseConfig,
 		column_names: &[&str],
-		block_opts: &BlockBasedOptions,
-	) -> io::Result<rocksdb::DB> {
+		block_opts: &BlockOptions,
+	) -> kvdb::Result<rocksdb::DB> {
 		let cf_descriptors: Vec<_> = (0..config.columns)
-			.map(|i| ColumnFamilyDescriptor::new(column_names[i as usize], config.column_config(&block_opts, i)))
+			.map(|i| ColumnFamilyDescriptor::new(column_names[i as usize], config.column_options(&block_opts, i)))
 			.collect();
 
 		let db = match DB::open_cf_descriptors(&opts, path, cf_descriptors) {
@@ -425,8 +876,8 @@ impl Database {
 					Ok(mut db) => {
 						for (i, name) in column_names.iter().enumerate() {
 							let _ = db
-								.create_cf(name, &config.column_config(&block_opts, i as u32))
-								.map_err(other_io_err)?;
+								.create_cf(name, &config.column_options(&block_opts, i as u32))
+								.map_err(map_rocksdb_err)?;
 						}
 						Ok(db)
 					}
@@ -438,40 +889,109 @@ impl Database {
 
 		Ok(match db {
 			Ok(db) => db,
-			Err(ref s) if is_corrupted(s) => {
+			Err(ref s) if is_corrupted(s) && config.repair_on_open => {
 				warn!("DB corrupted: {}, attempting repair", s);
-				DB::repair(&opts, path).map_err(other_io_err)?;
+				DB::repair(&opts, path).map_err(map_rocksdb_err)?;
 
 				let cf_descriptors: Vec<_> = (0..config.columns)
 					.map(|i| {
-						ColumnFamilyDescriptor::new(column_names[i as usize], config.column_config(&block_opts, i))
+						ColumnFamilyDescriptor::new(column_names[i as usize], config.column_options(&block_opts, i))
 					})
 					.collect();
 
-				DB::open_cf_descriptors(&opts, path, cf_descriptors).map_err(other_io_err)?
+				DB::open_cf_descriptors(&opts, path, cf_descriptors).map_err(map_rocksdb_err)?
 			}
-			Err(s) => return Err(other_io_err(s)),
+			Err(s) => return Err(map_rocksdb_err(s)),
 		})
 	}
 
+	/// Internal api to open a database whose columns are mapped by a column table, in any mode.
+	///
+	/// In primary mode, completes the changes of the columns interrupted by a crash: the columns of the table
+	/// without a column family are created, and the column families left out of the table are dropped.
+	/// Otherwise, the columns without a column family are left unused.
+	fn open_mapped(
+		opts: &Options,
+		path: &str,
+		config: &DatabaseConfig,
+		column_names: &mut columns::Table,
+		existing: &[String],
+		block_opts: &BlockOptions,
+	) -> kvdb::Result<rocksdb::DB> {
+		let exists = |name: &String| existing.contains(name);
+		if config.secondary.is_some() || config.read_only {
+			for name in column_names.iter_mut() {
+				if name.as_ref().filter(|name| exists(name)).is_none() {
+					*name = None;
+				}
+			}
+			let cf_descriptors = column_names.iter().enumerate().filter_map(|(i, name)| {
+				name.as_ref().map(|name| ColumnFamilyDescriptor::new(name, config.column_options(block_opts, i as u32)))
+			});
+			return match &config.secondary {
+				Some(secondary_path) => {
+					DB::open_cf_descriptors_as_secondary(opts, path, secondary_path.as_str(), cf_descriptors)
+				}
+				None => DB::open_cf_descriptors_read_only(opts, path, cf_descriptors, false),
+			}
+			.map_err(map_rocksdb_err);
+		}
+
+		let unused: Vec<&String> =
+			existing.iter().filter(|name| !columns::is_used(column_names, name.as_str())).collect();
+		let cf_descriptors: Vec<_> = column_names
+			.iter()
+			.enumerate()
+			.filter_map(|(i, name)| {
+				name.as_ref()
+					.filter(|name| exists(name))
+					.map(|name| ColumnFamilyDescriptor::new(name, config.column_options(block_opts, i as u32)))
+			})
+			.chain(unused.iter().map(|name| ColumnFamilyDescriptor::new(*name, Options::default())))
+			.chain(std::iter::once(ColumnFamilyDescriptor::new(columns::TABLE_COLUMN_FAMILY, Options::default())))
+			.collect();
+		let mut db = DB::open_cf_descriptors(opts, path, cf_descriptors).map_err(map_rocksdb_err)?;
+
+		for (i, name) in column_names.iter().enumerate() {
+			if let Some(name) = name.as_ref().filter(|name| !exists(name)) {
+				warn!("Creating the missing column family {} of column {}", name, i);
+				db.create_cf(name, &config.column_options(block_opts, i as u32)).map_err(map_rocksdb_err)?;
+			}
+		}
+		for name in unused {
+			warn!("Dropping the column family {} of a dropped column", name);
+			db.drop_cf(name).map_err(map_rocksdb_err)?;
+		}
+		Ok(db)
+	}
+
 	/// Internal api to open a database in secondary mode.
 	/// Secondary database needs a seperate path to store its own logs.
 	fn open_secondary(
 		opts: &Options,
 		path: &str,
 		secondary_path: &str,
+		config: &DatabaseConfig,
 		column_names: &[String],
-	) -> io::Result<rocksdb::DB> {
-		let db = DB::open_cf_as_secondary(&opts, path, secondary_path, column_names);
+		block_opts: &BlockOptions,
+	) -> kvdb::Result<rocksdb::DB> {
+		let cf_descriptors = || {
+			column_names
+				.iter()
+				.enumerate()
+				.map(|(i, name)| ColumnFamilyDescriptor::new(name, config.column_options(block_opts, i as u32)))
+		};
+		let db = DB::open_cf_descriptors_as_secondary(opts, path, secondary_path, cf_descriptors());
 
 		Ok(match db {
 			Ok(db) => db,
-			Err(ref s) if is_corrupted(s) => {
+			Err(ref s) if is_corrupted(s) && config.repair_on_open => {
 				warn!("DB corrupted: {}, attempting repair", s);
-				DB::repair(&opts, path).map_err(other_io_err)?;
-				DB::open_cf_as_secondary(&opts, path, secondary_path, column_names).map_err(other_io_err)?
+				DB::repair(&opts, path).map_err(map_rocksdb_err)?;
+				DB::open_cf_descriptors_as_secondary(opts, path, secondary_path, cf_descriptors())
+					.map_err(map_rocksdb_err)?
 			}
-			Err(s) => return Err(other_io_err(s)),
+			Err(s) => return Err(map_rocksdb_err(s)),
 		})
 	}
 
@@ -481,11 +1001,62 @@ impl Database {
 	}
 
 	/// Commit transaction to database.
-	pub fn write(&self, tr: DBTransaction) -> io::Result<()> {
+	pub fn write(&self, tr: DBTransaction) -> kvdb::Result<()> {
+		let _guard = self.commit_lock.read();
+		self.write_unguarded(tr, &self.write_opts)
+	}
+
+	/// Commit transaction to database with the given durability, overriding the one of the config.
+	pub fn write_with_durability(&self, tr: DBTransaction, durability: WriteDurability) -> kvdb::Result<()> {
+		durability.check()?;
+		let _guard = self.commit_lock.read();
+		self.write_unguarded(tr, &durability.write_options())
+	}
+
+	/// Commit an optimistic transaction to database, failing with a `TransactionConflict`
+	/// if any of the values it read has changed since.
+	pub fn write_optimistic(&self, tr: OptimisticTransaction) -> kvdb::Result<()> {
+		let _guard = self.commit_lock.write();
+		tr.validate(|col, key| self.get(col, key))?;
+		self.write_unguarded(tr.transaction, &self.write_opts)
+	}
+
+	// Fails if the transaction merges into a column with a time to live or inserts a value with
+	// an expiry into a column without one, as such values could not be read back.
+	fn check_expiries(&self, tr: &DBTransaction) -> kvdb::Result<()> {
+		for op in &tr.ops {
+			match *op {
+				DBOp::Merge { col, .. } if self.config.time_to_live.contains_key(&col) => {
+					return Err(kvdb::Error::NotSupported(format!("merge into column {} with a time to live", col)))
+				}
+				DBOp::InsertWithExpiry { col, .. } if !self.config.time_to_live.contains_key(&col) => {
+					return Err(kvdb::Error::NotSupported(format!("no time to live for column {}", col)))
+				}
+				_ => {}
+			}
+		}
+		Ok(())
+	}
+
+	// Fails if the database is opened read-only.
+	fn check_writable(&self) -> kvdb::Result<()> {
+		if self.config.read_only && self.config.secondary.is_none() {
+			return Err(kvdb::Error::NotSupported("the database is opened read-only".into()));
+		}
+		Ok(())
+	}
+
+	fn write_unguarded(&self, tr: DBTransaction, write_opts: &WriteOptions) -> kvdb::Result<()> {
+		self.check_writable()?;
+		self.check_expiries(&tr)?;
 		match *self.db.read() {
 			Some(ref cfs) => {
+				if let Some(op) = tr.ops.iter().find(|op| !cfs.has_column(op.col())) {
+					return Err(kvdb::Error::UnknownColumn(op.col()));
+				}
 				let mut batch = WriteBatch::default();
 				let ops = tr.ops;
+				let now = expiry::millis(SystemTime::now());
 
 				self.stats.tally_writes(ops.len() as u64);
 				self.stats.tally_transactions(1);
@@ -496,9 +1067,19 @@ impl Database {
 					let cf = cfs.cf(op.col() as usize);
 
 					match op {
-						DBOp::Insert { col: _, key, value } => {
+						DBOp::Insert { col, key, value } => {
+							stats_total_bytes += key.len() + value.len();
+							match self.config.time_to_live.get(&col) {
+								Some(ttl) => {
+									let expiry = now.saturating_add(ttl.as_millis() as u64);
+									batch.put_cf(cf, &key, &expiry::encode(expiry, &value));
+								}
+								None => batch.put_cf(cf, &key, &value),
+							}
+						}
+						DBOp::InsertWithExpiry { col: _, key, value, expiry } => {
 							stats_total_bytes += key.len() + value.len();
-							batch.put_cf(cf, &key, &value);
+							batch.put_cf(cf, &key, &expiry::encode(expiry::millis(expiry), &value));
 						}
 						DBOp::Delete { col: _, key } => {
 							// We count deletes as writes.
@@ -522,29 +1103,34 @@ impl Database {
 								}
 							}
 						}
+						DBOp::Merge { col: _, key, operand } => {
+							stats_total_bytes += key.len() + operand.len();
+							batch.merge_cf(cf, &key, &operand);
+						}
 					};
 				}
 				self.stats.tally_bytes_written(stats_total_bytes as u64);
 
-				check_for_corruption(&self.path, cfs.db.write_opt(batch, &self.write_opts))
+				check_for_corruption(&self.path, cfs.db.write_opt(batch, write_opts))
 			}
-			None => Err(other_io_err("Database is closed")),
+			None => Err(kvdb::Error::Closed),
 		}
 	}
 
 	/// Get value by key.
-	pub fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
+	pub fn get(&self, col: u32, key: &[u8]) -> kvdb::Result<Option<DBValue>> {
 		match *self.db.read() {
 			Some(ref cfs) => {
-				if cfs.column_names.get(col as usize).is_none() {
-					return Err(other_io_err("column index is out of bounds"));
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
 				}
 				self.stats.tally_reads(1);
+				let reader = self.expiry_reader(col);
 				let value = cfs
 					.db
 					.get_pinned_cf_opt(cfs.cf(col as usize), key, &self.read_opts)
-					.map(|r| r.map(|v| v.to_vec()))
-					.map_err(other_io_err);
+					.map(|r| r.and_then(|v| reader.value(&v).map(|v| v.to_vec())))
+					.map_err(map_rocksdb_err);
 
 				match value {
 					Ok(Some(ref v)) => self.stats.tally_bytes_read((key.len() + v.len()) as u64),
@@ -554,7 +1140,40 @@ impl Database {
 
 				value
 			}
-			None => Ok(None),
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Get the values of several keys at once, in the order of `keys`.
+	pub fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<kvdb::Result<Option<DBValue>>> {
+		match *self.db.read() {
+			Some(ref cfs) => {
+				if !cfs.has_column(col) {
+					return keys.iter().map(|_| Err(kvdb::Error::UnknownColumn(col))).collect();
+				}
+				let cf = cfs.cf(col as usize);
+				let reader = self.expiry_reader(col);
+				let mut bytes_read = 0;
+				let values = cfs
+					.db
+					.multi_get_cf_opt(keys.iter().map(|key| (cf, key)), &self.read_opts)
+					.into_iter()
+					.zip(keys)
+					.map(|(value, key)| {
+						let value = value.map(|r| r.and_then(|v| reader.value(&v).map(|v| v.to_vec())));
+						match value {
+							Ok(Some(ref v)) => bytes_read += key.len() + v.len(),
+							Ok(None) => bytes_read += key.len(),
+							_ => {}
+						};
+						value.map_err(map_rocksdb_err)
+					})
+					.collect();
+				self.stats.tally_reads(keys.len() as u64);
+				self.stats.tally_bytes_read(bytes_read as u64);
+				values
+			}
+			None => keys.iter().map(|_| Err(kvdb::Error::Closed)).collect(),
 		}
 	}
 
@@ -568,14 +1187,15 @@ impl Database {
 	/// preventing the database from being closed.
 	pub fn iter<'a>(&'a self, col: u32) -> impl Iterator<Item = KeyValuePair> + 'a {
 		let read_lock = self.db.read();
-		let optional = if read_lock.is_some() {
+		let optional = if matches!(*read_lock, Some(ref cfs) if cfs.has_column(col)) {
 			let read_opts = generate_read_options();
 			let guarded = iter::ReadGuardedIterator::new(read_lock, col, read_opts);
 			Some(guarded)
 		} else {
 			None
 		};
-		optional.into_iter().flat_map(identity)
+		let reader = self.expiry_reader(col);
+		optional.into_iter().flat_map(identity).filter_map(move |pair| reader.pair(pair))
 	}
 
 	/// Iterator over data in the `col` database column index matching the given prefix.
@@ -583,18 +1203,280 @@ impl Database {
 	/// preventing the database from being closed.
 	fn iter_with_prefix<'a>(&'a self, col: u32, prefix: &'a [u8]) -> impl Iterator<Item = iter::KeyValuePair> + 'a {
 		let read_lock = self.db.read();
-		let optional = if read_lock.is_some() {
+		let optional = if matches!(*read_lock, Some(ref cfs) if cfs.has_column(col)) {
+			let read_opts = self.config.prefix_read_options(col, prefix);
+			let guarded = iter::ReadGuardedIterator::new_with_prefix(read_lock, col, prefix, read_opts);
+			Some(guarded)
+		} else {
+			None
+		};
+		let reader = self.expiry_reader(col);
+		optional.into_iter().flat_map(identity).filter_map(move |pair| reader.pair(pair))
+	}
+
+	/// Iterator over data in the `col` database column index with keys within the given bounds,
+	/// in ascending key order.
+	/// Will hold a lock until the iterator is dropped
+	/// preventing the database from being closed.
+	pub fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&[u8]>,
+		end: Bound<&[u8]>,
+	) -> impl Iterator<Item = KeyValuePair> + 'a {
+		self.iter_range_directed(col, start, end, Direction::Forward)
+	}
+
+	/// Iterator over data in the `col` database column index with keys within the given bounds,
+	/// in descending key order.
+	/// Will hold a lock until the iterator is dropped
+	/// preventing the database from being closed.
+	pub fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&[u8]>,
+		end: Bound<&[u8]>,
+	) -> impl Iterator<Item = KeyValuePair> + 'a {
+		self.iter_range_directed(col, start, end, Direction::Reverse)
+	}
+
+	fn iter_range_directed<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&[u8]>,
+		end: Bound<&[u8]>,
+		direction: Direction,
+	) -> impl Iterator<Item = KeyValuePair> + 'a {
+		let (lower, upper) = iterate_bounds(start, end);
+		// rocksdb doesn't work with an empty upper bound
+		let is_empty = match (&lower, &upper) {
+			(_, Some(upper)) if upper.is_empty() => true,
+			(Some(lower), Some(upper)) => lower >= upper,
+			_ => false,
+		};
+		let read_lock = self.db.read();
+		let optional = if matches!(*read_lock, Some(ref cfs) if cfs.has_column(col)) && !is_empty {
 			let mut read_opts = generate_read_options();
-			// rocksdb doesn't work with an empty upper bound
-			if let Some(end_prefix) = kvdb::end_prefix(prefix) {
-				read_opts.set_iterate_upper_bound(end_prefix);
+			if let Some(lower) = lower {
+				read_opts.set_iterate_lower_bound(lower);
 			}
-			let guarded = iter::ReadGuardedIterator::new_with_prefix(read_lock, col, prefix, read_opts);
+			if let Some(upper) = upper {
+				read_opts.set_iterate_upper_bound(upper);
+			}
+			let guarded = iter::ReadGuardedIterator::new_with_range(read_lock, col, direction, read_opts);
 			Some(guarded)
 		} else {
 			None
 		};
-		optional.into_iter().flat_map(identity)
+		let reader = self.expiry_reader(col);
+		optional.into_iter().flat_map(identity).filter_map(move |pair| reader.pair(pair))
+	}
+
+	/// Take a consistent read-only snapshot of the database.
+	/// Will hold a lock until the snapshot is dropped
+	/// preventing the database from being closed.
+	pub fn snapshot(&self) -> Snapshot<'_> {
+		Snapshot::new(self.db.read(), &self.stats, &self.config)
+	}
+
+	// Decodes the values read from the column, hiding the expired ones.
+	fn expiry_reader(&self, col: u32) -> expiry::Reader {
+		expiry::Reader::new(self.config.time_to_live.contains_key(&col))
+	}
+
+	/// Drop the expired values of the columns with a time to live by compacting these columns.
+	///
+	/// Expired values are also dropped by the background compactions, but only when the
+	/// files holding them happen to be compacted.
+	pub fn compact_expired(&self) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.read() {
+			Some(ref cfs) => {
+				for col in self.config.time_to_live.keys() {
+					if cfs.has_column(*col) {
+						cfs.db.compact_range_cf(cfs.cf(*col as usize), None::<&[u8]>, None::<&[u8]>);
+					}
+				}
+				Ok(())
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Bulk load the key/value pairs, sorted by strictly increasing keys, into the column.
+	///
+	/// The pairs are written to SST files in `dir`, named after the column, which are then ingested
+	/// into the column, bypassing the write-ahead log and the memtables. The values of existing keys
+	/// are overwritten. The files are left in `dir` if `options.remove_files` is disabled.
+	pub fn bulk_load<I, K, V, P>(&self, col: u32, pairs: I, dir: P, options: &BulkLoadOptions) -> kvdb::Result<()>
+	where
+		I: IntoIterator<Item = (K, V)>,
+		K: AsRef<[u8]>,
+		V: AsRef<[u8]>,
+		P: AsRef<Path>,
+	{
+		self.check_writable()?;
+		let _guard = self.commit_lock.read();
+		match *self.db.read() {
+			Some(ref cfs) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
+				let opts = self.config.column_options(&self.block_opts, col);
+				let expiry = self
+					.config
+					.time_to_live
+					.get(&col)
+					.map(|ttl| expiry::millis(SystemTime::now()).saturating_add(ttl.as_millis() as u64));
+				let name = cfs.column_names[col as usize].as_ref().expect("the column is used; qed");
+				let files = bulk::build_files(&opts, dir.as_ref(), name, pairs, options, |value| match expiry {
+					Some(expiry) => Cow::Owned(expiry::encode(expiry, value)),
+					None => Cow::Borrowed(value),
+				})?;
+				if files.paths.is_empty() {
+					return Ok(());
+				}
+
+				let mut ingest_opts = IngestExternalFileOptions::default();
+				ingest_opts.set_move_files(options.remove_files);
+				let result =
+					cfs.db.ingest_external_file_cf_opts(cfs.cf(col as usize), &ingest_opts, files.paths.clone());
+				if options.remove_files {
+					files.remove();
+				}
+				check_for_corruption(&self.path, result)?;
+
+				self.stats.tally_writes(files.keys);
+				self.stats.tally_bytes_written(files.bytes);
+				self.stats.tally_transactions(1);
+				Ok(())
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Compact the keys of the column from `start` to `end`, `None` standing for the first and the last key.
+	///
+	/// Drops the tombstones left by the deletions, such as the ones of `DBOp::DeletePrefix`.
+	pub fn compact_range(&self, col: u32, start: Option<&[u8]>, end: Option<&[u8]>) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.read() {
+			Some(ref cfs) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
+				cfs.db.compact_range_cf(cfs.cf(col as usize), start, end);
+				Ok(())
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Compact all the keys of all the columns.
+	pub fn compact_all(&self) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.read() {
+			Some(ref cfs) => {
+				for col in cfs.columns() {
+					cfs.db.compact_range_cf(cfs.cf(col), None::<&[u8]>, None::<&[u8]>);
+				}
+				Ok(())
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Flush the memtables of the column to disk.
+	pub fn flush(&self, col: u32) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.read() {
+			Some(ref cfs) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
+				cfs.db.flush_cf(cfs.cf(col as usize)).map_err(map_rocksdb_err)
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Flush the write-ahead log to disk and sync it, making all the previous writes durable.
+	pub fn flush_wal(&self) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.read() {
+			Some(ref cfs) => cfs.db.flush_wal(true).map_err(map_rocksdb_err),
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Create a checkpoint of the database at the given path, which must not exist.
+	///
+	/// The checkpoint is a consistent copy of the database, hard linking its files when on the same
+	/// filesystem, which can be opened with `Database::open`.
+	pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> kvdb::Result<()> {
+		match *self.db.read() {
+			Some(ref cfs) => rocksdb::checkpoint::Checkpoint::new(&cfs.db)
+				.and_then(|checkpoint| checkpoint.create_checkpoint(path))
+				.map_err(map_rocksdb_err),
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Create a consistent backup of the database with the given backup engine,
+	/// returning the identifier of the backup.
+	///
+	/// Only the files changed since the previous backup of the engine are copied.
+	pub fn backup(&self, engine: &mut BackupEngine) -> kvdb::Result<u32> {
+		match *self.db.read() {
+			Some(ref cfs) => engine.create(&cfs.db),
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Verify the integrity of the database, reporting the corrupted SST files and the key ranges of the columns
+	/// which could not be read.
+	///
+	/// Checks that the live SST files exist with the expected sizes. A `deep` verification also reads all the keys
+	/// of the columns, verifying the checksums of the blocks which are not already in the block cache.
+	/// The corruptions found are left as they are, until the database is repaired with `repair`.
+	pub fn verify(&self, deep: bool) -> kvdb::Result<VerifyReport> {
+		match *self.db.read() {
+			Some(ref cfs) => cfs.verify(Path::new(&self.path), deep),
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Repair the database, salvaging as much data as possible from the corrupted SST files,
+	/// which may lose the keys they hold. The database is closed during the repair, and reopened
+	/// even if the repair fails. If it cannot be reopened, it is left closed: the calls fail with
+	/// `Error::Closed` until a later `repair` reopens it.
+	pub fn repair(&self) -> kvdb::Result<()> {
+		self.check_writable()?;
+		self.close();
+
+		warn!("Repairing the database at {}", self.path);
+		let repaired = DB::repair(&self.opts, &self.path).map_err(map_rocksdb_err);
+		match repaired {
+			Ok(()) => {
+				// ignore errors
+				let _ = fs::remove_file(Path::new(&self.path).join(Database::CORRUPTION_FILE_NAME));
+				info!("Repaired the database at {}", self.path);
+			}
+			Err(ref err) => warn!("Failed to repair the database at {}: {}", self.path, err),
+		}
+
+		// reopen the database, even if it is still marked as corrupted, and steal handles into self
+		let reopened = generate_block_based_options(&self.config).and_then(|block_opts| {
+			Self::open_with(&self.config, &self.path, generate_options(&self.config), block_opts)
+		});
+		match reopened {
+			Ok(db) => *self.db.write() = mem::replace(&mut *db.db.write(), None),
+			Err(err) => {
+				warn!("Failed to reopen the database at {}: {}", self.path, err);
+				return repaired.and(Err(err));
+			}
+		}
+		repaired
 	}
 
 	/// Close the database
@@ -603,7 +1485,8 @@ impl Database {
 	}
 
 	/// Restore the database from a copy at given path.
-	pub fn restore(&self, new_db: &str) -> io::Result<()> {
+	pub fn restore(&self, new_db: &str) -> kvdb::Result<()> {
+		self.check_writable()?;
 		self.close();
 
 		// swap is guaranteed to be atomic
@@ -621,10 +1504,7 @@ impl Database {
 					}
 					Err(err) => {
 						warn!("Failed to swap DB directories: {:?}", err);
-						return Err(io::Error::new(
-							io::ErrorKind::Other,
-							"DB restoration failed: could not swap DB directories",
-						));
+						return Err(kvdb::Error::Other("DB restoration failed: could not swap DB directories".into()));
 					}
 				}
 			}
@@ -637,6 +1517,9 @@ impl Database {
 	}
 
 	/// The number of column families in the db.
+	///
+	/// Once columns have been dropped with `drop_column` or moved with `move_column`, this is the number of
+	/// column indices, including the unused ones, which the database must be reopened with.
 	pub fn num_columns(&self) -> u32 {
 		self.db
 			.read()
@@ -647,14 +1530,17 @@ impl Database {
 	}
 
 	/// The number of keys in a column (estimated).
-	pub fn num_keys(&self, col: u32) -> io::Result<u64> {
+	pub fn num_keys(&self, col: u32) -> kvdb::Result<u64> {
 		const ESTIMATE_NUM_KEYS: &str = "rocksdb.estimate-num-keys";
 		match *self.db.read() {
 			Some(ref cfs) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
 				let cf = cfs.cf(col as usize);
 				match cfs.db.property_int_value_cf(cf, ESTIMATE_NUM_KEYS) {
 					Ok(estimate) => Ok(estimate.unwrap_or_default()),
-					Err(err_string) => Err(other_io_err(err_string)),
+					Err(err_string) => Err(map_rocksdb_err(err_string)),
 				}
 			}
 			None => Ok(0),
@@ -662,39 +1548,163 @@ impl Database {
 	}
 
 	/// Remove the last column family in the database. The deletion is definitive.
-	pub fn remove_last_column(&self) -> io::Result<()> {
+	pub fn remove_last_column(&self) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.write() {
+			Some(ref mut cfs) => match cfs.column_names.len() {
+				0 => Ok(()),
+				len => cfs.drop_column(len - 1),
+			},
+			None => Ok(()),
+		}
+	}
+
+	/// Remove the column with the given index, along with its data. The deletion is definitive.
+	///
+	/// The other columns keep their indices: unless it is the last one, the index of the column is left
+	/// unused, and can be reused by `move_column`. The mapping of the column indices to the column families
+	/// is then persisted in the database. Reads of an unused index fail with `UnknownColumn`, while iterations
+	/// over it yield nothing.
+	pub fn drop_column(&self, col: u32) -> kvdb::Result<()> {
+		self.check_writable()?;
+		match *self.db.write() {
+			Some(ref mut cfs) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
+				cfs.drop_column(col as usize)
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// Move the column with index `from`, along with its data, to the unused index `to`, leaving `from`
+	/// unused. The mapping of the column indices to the column families is then persisted in the database.
+	///
+	/// The column keeps the options it was opened with until the database is reopened, after which
+	/// the settings of `DatabaseConfig` for `to` apply to it. Both columns must either have
+	/// a time to live or none, as it changes the encoding of the values.
+	pub fn move_column(&self, from: u32, to: u32) -> kvdb::Result<()> {
+		self.check_writable()?;
+		if self.config.time_to_live.contains_key(&from) != self.config.time_to_live.contains_key(&to) {
+			return Err(kvdb::Error::NotSupported(format!(
+				"cannot move column {} to {}, only one of them has a time to live",
+				from, to
+			)));
+		}
 		match *self.db.write() {
 			Some(DBAndColumns { ref mut db, ref mut column_names }) => {
-				if let Some(name) = column_names.pop() {
-					db.drop_cf(&name).map_err(other_io_err)?;
+				let mut names = column_names.clone();
+				let name = match names.get_mut(from as usize).and_then(Option::take) {
+					Some(name) => name,
+					None => return Err(kvdb::Error::UnknownColumn(from)),
+				};
+				if names.len() <= to as usize {
+					names.resize(to as usize + 1, None);
+				}
+				if names[to as usize].is_some() {
+					return Err(kvdb::Error::Other(format!("cannot move column {} to {}, which is used", from, to)));
 				}
+				names[to as usize] = Some(name);
+				trim_unused(&mut names);
+				columns::write(db, &names)?;
+				*column_names = names;
 				Ok(())
 			}
-			None => Ok(()),
+			None => Err(kvdb::Error::Closed),
 		}
 	}
 
 	/// Add a new column family to the DB.
-	pub fn add_column(&self) -> io::Result<()> {
+	pub fn add_column(&self) -> kvdb::Result<()> {
+		self.check_writable()?;
 		match *self.db.write() {
 			Some(DBAndColumns { ref mut db, ref mut column_names }) => {
 				let col = column_names.len() as u32;
-				let name = format!("col{}", col);
-				let col_config = self.config.column_config(&self.block_opts, col as u32);
-				let _ = db.create_cf(&name, &col_config).map_err(other_io_err)?;
-				column_names.push(name);
+				let name = columns::unused_name(column_names);
+				let mut names = column_names.clone();
+				names.push(Some(name.clone()));
+				// the column family is created at the next opening if this fails
+				columns::write(db, &names)?;
+				let col_config = self.config.column_options(&self.block_opts, col as u32);
+				let _ = db.create_cf(&name, &col_config).map_err(map_rocksdb_err)?;
+				*column_names = names;
 				Ok(())
 			}
 			None => Ok(()),
 		}
 	}
 
+	/// Get the RocksDB properties of a column.
+	pub fn column_properties(&self, col: u32) -> kvdb::Result<ColumnProperties> {
+		match *self.db.read() {
+			Some(ref cfs) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
+				Ok(cfs.column_properties(col as usize))
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
+	/// List the live SST files of the database.
+	///
+	/// The files of all the columns are listed, as RocksDB does not report the column of the files.
+	pub fn live_files(&self) -> kvdb::Result<Vec<LiveFile>> {
+		match *self.db.read() {
+			Some(ref cfs) => {
+				let files = cfs.db.live_files().map_err(map_rocksdb_err)?;
+				Ok(files.into_iter().map(LiveFile::from).collect())
+			}
+			None => Err(kvdb::Error::Closed),
+		}
+	}
+
 	/// Get RocksDB statistics.
+	///
+	/// Along with the native statistics, when enabled, reports the number of columns with a compaction
+	/// pending as `compaction-pending` and the number of compactions running as `num-running-compactions`.
 	pub fn get_statistics(&self) -> HashMap<String, stats::RocksDbStatsValue> {
-		if let Some(stats) = self.opts.get_statistics() {
+		let mut statistics = if let Some(stats) = self.opts.get_statistics() {
 			stats::parse_rocksdb_stats(&stats)
 		} else {
 			HashMap::new()
+		};
+		if let Some(ref cfs) = *self.db.read() {
+			const COMPACTION_PENDING: &str = "rocksdb.compaction-pending";
+			const NUM_RUNNING_COMPACTIONS: &str = "rocksdb.num-running-compactions";
+
+			let pending = cfs
+				.columns()
+				.map(|col| cfs.db.property_int_value_cf(cfs.cf(col), COMPACTION_PENDING).ok().flatten().unwrap_or(0))
+				.sum();
+			let running = cfs.db.property_int_value(NUM_RUNNING_COMPACTIONS).ok().flatten().unwrap_or(0);
+			for (name, count) in [(COMPACTION_PENDING, pending), (NUM_RUNNING_COMPACTIONS, running)].iter() {
+				let value = stats::RocksDbStatsValue { count: *count, times: None };
+				statistics.insert(name.trim_start_matches("rocksdb.").to_owned(), value);
+			}
+		}
+		statistics
+	}
+
+	/// Get the write stall condition of the database, when the flushes and compactions fall behind the writes.
+	pub fn write_stall(&self) -> kvdb::Result<kvdb::WriteStall> {
+		const IS_WRITE_STOPPED: &str = "rocksdb.is-write-stopped";
+		const ACTUAL_DELAYED_WRITE_RATE: &str = "rocksdb.actual-delayed-write-rate";
+
+		match *self.db.read() {
+			Some(ref cfs) => {
+				let property = |name| cfs.db.property_int_value(name).map_err(map_rocksdb_err);
+				if property(IS_WRITE_STOPPED)?.unwrap_or(0) != 0 {
+					return Ok(kvdb::WriteStall::Stopped);
+				}
+				Ok(match property(ACTUAL_DELAYED_WRITE_RATE)?.unwrap_or(0) {
+					0 => kvdb::WriteStall::None,
+					rate => kvdb::WriteStall::Delayed(rate),
+				})
+			}
+			None => Err(kvdb::Error::Closed),
 		}
 	}
 
@@ -718,9 +1728,9 @@ impl Database {
 	/// this method fails silently and no error is returned.
 	///
 	/// Calling this as primary will return an error.
-	pub fn try_catch_up_with_primary(&self) -> io::Result<()> {
+	pub fn try_catch_up_with_primary(&self) -> kvdb::Result<()> {
 		match self.db.read().as_ref() {
-			Some(DBAndColumns { db, .. }) => db.try_catch_up_with_primary().map_err(other_io_err),
+			Some(DBAndColumns { db, .. }) => db.try_catch_up_with_primary().map_err(map_rocksdb_err),
 			None => Ok(()),
 		}
 	}
@@ -729,18 +1739,26 @@ impl Database {
 // duplicate declaration of methods here to avoid trait import in certain existing cases
 // at time of addition.
 impl KeyValueDB for Database {
-	fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
+	fn get(&self, col: u32, key: &[u8]) -> kvdb::Result<Option<DBValue>> {
 		Database::get(self, col, key)
 	}
 
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<kvdb::Result<Option<DBValue>>> {
+		Database::get_many(self, col, keys)
+	}
+
 	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
 		Database::get_by_prefix(self, col, prefix)
 	}
 
-	fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+	fn write(&self, transaction: DBTransaction) -> kvdb::Result<()> {
 		Database::write(self, transaction)
 	}
 
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> kvdb::Result<()> {
+		Database::write_optimistic(self, transaction)
+	}
+
 	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
 		let unboxed = Database::iter(self, col);
 		Box::new(unboxed.into_iter())
@@ -751,17 +1769,44 @@ impl KeyValueDB for Database {
 		Box::new(unboxed.into_iter())
 	}
 
-	fn restore(&self, new_db: &str) -> io::Result<()> {
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
+		let unboxed = Database::iter_range(self, col, start, end);
+		Box::new(unboxed.into_iter())
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
+		let unboxed = Database::iter_range_rev(self, col, start, end);
+		Box::new(unboxed.into_iter())
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		Box::new(Database::snapshot(self))
+	}
+
+	fn restore(&self, new_db: &str) -> kvdb::Result<()> {
 		Database::restore(self, new_db)
 	}
 
+	fn compact_expired(&self) -> kvdb::Result<()> {
+		Database::compact_expired(self)
+	}
+
 	fn io_stats(&self, kind: kvdb::IoStatsKind) -> kvdb::IoStats {
 		let rocksdb_stats = self.get_statistics();
 		let cache_hit_count = rocksdb_stats.get("block.cache.hit").map(|s| s.count).unwrap_or(0u64);
-		let overall_stats = self.stats.overall();
-		let old_cache_hit_count = overall_stats.raw.cache_hit_count;
-
-		self.stats.tally_cache_hit_count(cache_hit_count - old_cache_hit_count);
+		self.stats.tally_cache_hit_ticker(cache_hit_count);
+		let stall_micros = rocksdb_stats.get("stall.micros").map(|s| s.count).unwrap_or(0u64);
+		self.stats.tally_stall_micros_ticker(stall_micros);
 
 		let taken_stats = match kind {
 			kvdb::IoStatsKind::Overall => self.stats.overall(),
@@ -776,6 +1821,8 @@ impl KeyValueDB for Database {
 		stats.bytes_written = taken_stats.raw.bytes_written;
 		stats.bytes_read = taken_stats.raw.bytes_read;
 		stats.cache_reads = taken_stats.raw.cache_hit_count;
+		stats.write_stall_duration = Duration::from_micros(taken_stats.raw.stall_micros);
+		stats.write_stall = self.write_stall().unwrap_or_default();
 		stats.started = taken_stats.started;
 		stats.span = taken_stats.started.elapsed();
 
@@ -790,7 +1837,7 @@ mod tests {
 	use std::io::{self, Read};
 	use tempdir::TempDir;
 
-	fn create(columns: u32) -> io::Result<Database> {
+	fn create(columns: u32) -> kvdb::Result<Database> {
 		let tempdir = TempDir::new("")?;
 		let config = DatabaseConfig::with_columns(columns);
 		Database::open(&config, tempdir.path().to_str().expect("tempdir path is valid unicode"))
@@ -808,6 +1855,202 @@ mod tests {
 		st::test_put_and_get(&db)
 	}
 
+	#[test]
+	fn get_many() -> io::Result<()> {
+		let db = create(1)?;
+		st::test_get_many(&db)?;
+
+		db.io_stats(kvdb::IoStatsKind::SincePrevious);
+		let keys: [&[u8]; 3] = [b"key1", b"key2", b"key3"];
+		db.get_many(0, &keys);
+		let io_stats = db.io_stats(kvdb::IoStatsKind::SincePrevious);
+		assert_eq!(io_stats.reads, 3);
+		assert_eq!(io_stats.bytes_read, 4 + 5 + 4 + 4 + 3);
+		Ok(())
+	}
+
+	#[test]
+	fn write_fails_when_closed() -> io::Result<()> {
+		let db = create(1)?;
+		db.close();
+		assert!(matches!(db.write(db.transaction()), Err(kvdb::Error::Closed)));
+		Ok(())
+	}
+
+	#[test]
+	fn write_durability() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let mut config = DatabaseConfig::with_columns(1);
+		config.write_durability.disable_wal = true;
+		{
+			let db = Database::open(&config, path)?;
+			let mut batch = db.transaction();
+			batch.put(0, b"key1", b"horse");
+			db.write(batch)?;
+
+			let mut batch = db.transaction();
+			batch.put(0, b"key2", b"cat");
+			db.write_with_durability(batch, WriteDurability { sync: true, no_slowdown: true, ..Default::default() })?;
+
+			let mut batch = db.transaction();
+			batch.put(0, b"key3", b"pigeon");
+			let durability = WriteDurability { sync: true, disable_wal: true, ..Default::default() };
+			assert!(matches!(db.write_with_durability(batch, durability), Err(kvdb::Error::NotSupported(_))));
+			assert_eq!(&*db.get(0, b"key1")?.unwrap(), b"horse");
+			assert!(db.get(0, b"key3")?.is_none());
+		}
+		let db = Database::open(&config, path)?;
+		assert_eq!(&*db.get(0, b"key2")?.unwrap(), b"cat");
+		drop(db);
+
+		config.write_durability.sync = true;
+		assert!(matches!(Database::open(&config, path), Err(kvdb::Error::NotSupported(_))));
+		Ok(())
+	}
+
+	#[test]
+	fn io_limits() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let mut config = DatabaseConfig::with_columns(2);
+		config.enable_statistics = true;
+		config.io_limits = IoLimits {
+			rate_limit: Some(64 * MB as i64),
+			max_background_jobs: Some(3),
+			db_write_buffer_size: Some(32 * MB),
+			max_write_buffer_number: Some(5),
+			level0_slowdown_writes_trigger: Some(30),
+			level0_stop_writes_trigger: Some(40),
+			soft_pending_compaction_bytes_limit: Some(1 << 30),
+			hard_pending_compaction_bytes_limit: Some(4 << 30),
+		};
+		let db = Database::open(&config, path)?;
+		let mut batch = db.transaction();
+		batch.put(1, b"key1", b"horse");
+		db.write(batch)?;
+		db.flush(1)?;
+		assert_eq!(&*db.get(1, b"key1")?.unwrap(), b"horse");
+
+		assert_eq!(db.write_stall()?, kvdb::WriteStall::None);
+		let stats = db.io_stats(kvdb::IoStatsKind::Overall);
+		assert_eq!(stats.write_stall, kvdb::WriteStall::None);
+		assert_eq!(stats.write_stall_duration, Duration::default());
+
+		// the options RocksDB persists along with the database
+		let options_file = fs::read_dir(path)?
+			.filter_map(|entry| entry.ok())
+			.filter(|entry| entry.file_name().to_string_lossy().starts_with("OPTIONS-"))
+			.map(|entry| entry.path())
+			.max()
+			.expect("RocksDB persists its options; qed");
+		let options = fs::read_to_string(options_file)?;
+		for option in &[
+			"max_background_jobs=3",
+			"db_write_buffer_size=33554432",
+			"max_write_buffer_number=5",
+			"level0_slowdown_writes_trigger=30",
+			"level0_stop_writes_trigger=40",
+			"soft_pending_compaction_bytes_limit=1073741824",
+			"hard_pending_compaction_bytes_limit=4294967296",
+		] {
+			assert!(options.contains(option), "{} is not set", option);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn verify_and_repair() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let config = DatabaseConfig::with_columns(2);
+		let file = {
+			let db = Database::open(&config, path)?;
+			let mut batch = db.transaction();
+			for i in 0..100u32 {
+				batch.put(0, format!("a{:03}", i).as_bytes(), b"horse");
+				batch.put(1, format!("b{:03}", i).as_bytes(), b"pigeon");
+			}
+			db.write(batch)?;
+			db.flush(0)?;
+			db.flush(1)?;
+			let report = db.verify(true)?;
+			assert!(report.is_ok());
+			assert_eq!(report.keys_checked, 200);
+
+			let files = db.live_files()?;
+			files.into_iter().find(|file| file.smallest_key.as_deref() == Some(b"a000")).unwrap()
+		};
+
+		// corrupt the first data block of the file of column 0
+		let file_path = tempdir.path().join(file.name.trim_start_matches('/'));
+		let mut content = fs::read(&file_path)?;
+		content[10] ^= 0xff;
+		fs::write(&file_path, content)?;
+
+		let db = Database::open(&config, path)?;
+		assert!(db.verify(false)?.is_ok());
+		let report = db.verify(true)?;
+		assert_eq!(report.keys_checked, 100);
+		assert_eq!(report.bad_files.len(), 1);
+		assert_eq!(report.bad_files[0].file.name, file.name);
+		assert_eq!(report.bad_ranges.len(), 1);
+		assert_eq!(report.bad_ranges[0].col, 0);
+		assert_eq!(report.bad_ranges[0].after, None);
+		assert_eq!(report.bad_ranges[0].until.as_deref(), Some(&b"a099"[..]));
+
+		db.repair()?;
+		assert!(db.verify(true)?.is_ok());
+		assert_eq!(&*db.get(1, b"b042")?.unwrap(), b"pigeon");
+		Ok(())
+	}
+
+	#[test]
+	fn repair_failure() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().join("db");
+		let db = Database::open(&DatabaseConfig::with_columns(1), path.to_str().expect("valid unicode"))?;
+		// neither repairable nor reopenable
+		fs::remove_dir_all(&path)?;
+		fs::write(&path, b"")?;
+		assert!(db.repair().is_err());
+		assert!(matches!(db.get(0, b"key"), Err(kvdb::Error::Closed)));
+
+		// a later repair reopens the database
+		fs::remove_file(&path)?;
+		fs::create_dir(&path)?;
+		db.repair()?;
+		assert!(db.get(0, b"key")?.is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn repair_on_open() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let config = DatabaseConfig::with_columns(1);
+		{
+			let db = Database::open(&config, path)?;
+			let mut batch = db.transaction();
+			batch.put(0, b"key1", b"horse");
+			db.write(batch)?;
+			db.flush(0)?;
+		}
+		let marker = tempdir.path().join(Database::CORRUPTION_FILE_NAME);
+		fs::File::create(&marker)?;
+
+		assert!(matches!(Database::open(&config, path), Err(kvdb::Error::Corruption(_))));
+		assert!(marker.exists());
+		let read_only = Database::open(&DatabaseConfig { read_only: true, ..config.clone() }, path)?;
+		assert_eq!(&*read_only.get(0, b"key1")?.unwrap(), b"horse");
+		drop(read_only);
+
+		let db = Database::open(&DatabaseConfig { repair_on_open: true, ..config }, path)?;
+		assert!(!marker.exists());
+		assert_eq!(&*db.get(0, b"key1")?.unwrap(), b"horse");
+		Ok(())
+	}
+
 	#[test]
 	fn delete_and_get() -> io::Result<()> {
 		let db = create(1)?;
@@ -832,6 +2075,30 @@ mod tests {
 		st::test_iter_with_prefix(&db)
 	}
 
+	#[test]
+	fn iter_range() -> io::Result<()> {
+		let db = create(1)?;
+		st::test_iter_range(&db)
+	}
+
+	#[test]
+	fn iter_range_rev() -> io::Result<()> {
+		let db = create(1)?;
+		st::test_iter_range_rev(&db)
+	}
+
+	#[test]
+	fn snapshot() -> io::Result<()> {
+		let db = create(1)?;
+		st::test_snapshot(&db)
+	}
+
+	#[test]
+	fn optimistic_transaction() -> io::Result<()> {
+		let db = create(1)?;
+		st::test_optimistic_transaction(&db)
+	}
+
 	#[test]
 	fn complex() -> io::Result<()> {
 		let db = create(1)?;
@@ -844,6 +2111,416 @@ mod tests {
 		st::test_io_stats(&db)
 	}
 
+	#[test]
+	fn concurrent_stats() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let config = DatabaseConfig { enable_statistics: true, ..DatabaseConfig::with_columns(1) };
+		let db = Database::open(&config, tempdir.path().to_str().expect("tempdir path is valid unicode"))?;
+		let mut batch = db.transaction();
+		batch.put(0, b"key1", b"horse");
+		db.write(batch)?;
+		db.flush(0)?;
+
+		std::thread::scope(|scope| {
+			for _ in 0..4 {
+				scope.spawn(|| {
+					for _ in 0..50 {
+						assert!(db.get(0, b"key1").unwrap().is_some());
+						db.io_stats(kvdb::IoStatsKind::SincePrevious);
+					}
+				});
+			}
+		});
+		// every cache hit of the ticker is tallied exactly once
+		let hits = db.get_statistics().get("block.cache.hit").map(|s| s.count).unwrap_or(0);
+		assert_eq!(db.io_stats(kvdb::IoStatsKind::Overall).cache_reads, hits);
+		Ok(())
+	}
+
+	#[test]
+	fn export_import() -> io::Result<()> {
+		let db = create(2)?;
+		st::test_export_import(&db, &create(2)?)
+	}
+
+	#[test]
+	fn migrations() -> io::Result<()> {
+		let db = create(3)?;
+		st::test_migrations(&db)
+	}
+
+	#[test]
+	fn merge() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let mut config = DatabaseConfig::with_columns(1);
+		config.merge_operators.insert(0, ColumnMergeOperator::new::<st::AppendOperator>());
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		{
+			let db = Database::open(&config, path)?;
+			st::test_merge(&db)?;
+			let mut batch = db.transaction();
+			batch.merge(0, b"list", b"z");
+			db.write(batch)?;
+		}
+		let db = Database::open(&config, path)?;
+		assert_eq!(&*db.get(0, b"list")?.unwrap(), b"xyz");
+		Ok(())
+	}
+
+	#[test]
+	fn merge_read_only_and_secondary() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let secondary = TempDir::new("")?;
+		let mut config = DatabaseConfig::with_columns(2);
+		config.merge_operators.insert(1, ColumnMergeOperator::new::<st::AppendOperator>());
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let db = Database::open(&config, path)?;
+		let mut batch = db.transaction();
+		batch.merge(1, b"list", b"x");
+		batch.merge(1, b"list", b"y");
+		db.write(batch)?;
+		db.flush(1)?;
+
+		let read_only = DatabaseConfig { read_only: true, ..config.clone() };
+		let secondary =
+			DatabaseConfig { secondary: secondary.path().to_str().map(|s| s.to_string()), ..config.clone() };
+		let check = || -> io::Result<()> {
+			for config in &[&read_only, &secondary] {
+				let db = Database::open(config, path)?;
+				assert_eq!(&*db.get(1, b"list")?.unwrap(), b"xy");
+			}
+			Ok(())
+		};
+		check()?;
+		// with a column table
+		db.drop_column(0)?;
+		check()
+	}
+
+	#[test]
+	fn expiry() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let mut config = DatabaseConfig::with_columns(2);
+		config.time_to_live.insert(0, Duration::from_secs(3600));
+		let db = Database::open(&config, tempdir.path().to_str().expect("tempdir path is valid unicode"))?;
+		st::test_expiry(&db)?;
+
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let mut config = DatabaseConfig::with_columns(1);
+		config.time_to_live.insert(0, Duration::from_millis(1));
+		{
+			let db = Database::open(&config, path)?;
+			let mut batch = db.transaction();
+			batch.put(0, b"key1", b"horse");
+			db.write(batch)?;
+			std::thread::sleep(Duration::from_millis(10));
+			assert!(db.get(0, b"key1")?.is_none());
+			assert!(db.snapshot().get(0, b"key1")?.is_none());
+			db.compact_expired()?;
+
+			let mut batch = db.transaction();
+			batch.merge(0, b"key1", b"operand");
+			assert!(matches!(db.write(batch), Err(kvdb::Error::NotSupported(_))));
+		}
+		// the expired value has been dropped from storage
+		let db = Database::open(&DatabaseConfig::with_columns(1), path)?;
+		assert!(db.iter(0).next().is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn column_config() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let mut config = DatabaseConfig::with_columns(2);
+		config.column_config.insert(
+			0,
+			ColumnConfig {
+				prefix_extractor_len: Some(3),
+				compaction_style: CompactionStyle::Universal,
+				..Default::default()
+			},
+		);
+		config.column_config.insert(
+			1,
+			ColumnConfig {
+				compression: Compression::None,
+				bloom_bits: None,
+				block_size: Some(4096),
+				optimize_for_point_lookup: true,
+				..Default::default()
+			},
+		);
+		config.column_config.insert(
+			2,
+			ColumnConfig {
+				compaction_style: CompactionStyle::Fifo { max_table_files_size: 1 << 30 },
+				..Default::default()
+			},
+		);
+		let db = Database::open(&config, path)?;
+		db.add_column()?;
+		st::test_iter_with_prefix(&db)?;
+
+		// an iteration bound to the extracted prefix of "abc" stops before "abz" only if the prefix is 3 bytes long
+		let mut batch = db.transaction();
+		batch.put(0, b"abz", b"abz");
+		db.write(batch)?;
+		let mut read_opts = ReadOptions::default();
+		read_opts.set_prefix_same_as_start(true);
+		let keys: Vec<_> = {
+			let cfs = db.db.read();
+			let cfs = cfs.as_ref().expect("the database is open; qed");
+			let from = rocksdb::IteratorMode::From(b"abc", Direction::Forward);
+			cfs.db.iterator_cf_opt(cfs.cf(0), read_opts, from).map(|(key, _)| key.into_vec()).collect()
+		};
+		assert_eq!(keys, vec![b"abc".to_vec(), b"abcd".to_vec()]);
+
+		let mut settings = String::new();
+		fs::File::open(tempdir.path().join("LOG"))?.read_to_string(&mut settings)?;
+		assert!(settings.contains("Options.prefix_extractor: rocksdb.FixedPrefix"));
+		assert!(settings.contains("Options.compaction_style: kCompactionStyleUniversal"));
+		assert!(settings.contains("Options.compaction_style: kCompactionStyleFIFO"));
+		assert!(settings.contains("Options.compression: NoCompression"));
+		assert!(settings.contains(" block_size: 4096"));
+		assert!(settings.contains("data_block_index_type: 1"));
+		drop(db);
+
+		let secondary = TempDir::new("")?;
+		let config = DatabaseConfig { columns: 3, ..config };
+		let read_only = DatabaseConfig { read_only: true, ..config.clone() };
+		let secondary_config = DatabaseConfig { secondary: secondary.path().to_str().map(|s| s.to_string()), ..config };
+		for (config, log) in
+			&[(read_only, tempdir.path().join("LOG")), (secondary_config, secondary.path().join("LOG"))]
+		{
+			let _db = Database::open(config, path)?;
+			let mut settings = String::new();
+			fs::File::open(log)?.read_to_string(&mut settings)?;
+			assert!(settings.contains("Options.compaction_style: kCompactionStyleUniversal"));
+			assert!(settings.contains(" block_size: 4096"));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn compaction() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let mut config = DatabaseConfig::with_columns(2);
+		config.enable_statistics = true;
+		let db = Database::open(&config, tempdir.path().to_str().expect("tempdir path is valid unicode"))?;
+		let mut batch = db.transaction();
+		for i in 0u32..1000 {
+			batch.put(0, &i.to_be_bytes(), b"horse");
+		}
+		batch.put(1, b"key1", b"cat");
+		db.write(batch)?;
+		let wal_syncs = || db.get_statistics().get("wal.synced").map(|s| s.count).unwrap_or(0);
+		let synced = wal_syncs();
+		db.flush_wal()?;
+		assert!(wal_syncs() > synced);
+		db.flush(0)?;
+		let mut batch = db.transaction();
+		batch.delete_prefix(0, &[0, 0]);
+		db.write(batch)?;
+		db.flush(0)?;
+
+		db.compact_range(0, None, Some(&[0, 0, 2]))?;
+		db.compact_range(0, Some(&[0, 0, 2]), None)?;
+		db.compact_all()?;
+		assert!(db.iter(0).next().is_none());
+		assert_eq!(&*db.get(1, b"key1")?.unwrap(), b"cat");
+		assert!(matches!(db.compact_range(2, None, None), Err(kvdb::Error::UnknownColumn(2))));
+		assert!(matches!(db.flush(2), Err(kvdb::Error::UnknownColumn(2))));
+
+		let statistics = db.get_statistics();
+		assert_eq!(statistics.get("compaction-pending").map(|s| s.count), Some(0));
+		assert!(statistics.contains_key("num-running-compactions"));
+
+		db.close();
+		assert!(matches!(db.compact_all(), Err(kvdb::Error::Closed)));
+		assert!(matches!(db.flush_wal(), Err(kvdb::Error::Closed)));
+		Ok(())
+	}
+
+	#[test]
+	fn bulk_load() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let mut config = DatabaseConfig::with_columns(2);
+		config.time_to_live.insert(1, Duration::from_secs(3600));
+		let db = Database::open(&config, tempdir.path().join("db").to_str().expect("valid unicode"))?;
+		let dir = tempdir.path().join("sst");
+		let files = || -> io::Result<usize> { Ok(fs::read_dir(&dir)?.count()) };
+
+		let mut batch = db.transaction();
+		batch.put(0, &5u32.to_be_bytes(), b"old");
+		batch.put(0, b"other", b"value");
+		db.write(batch)?;
+
+		let pairs = (0u32..10_000).map(|i| (i.to_be_bytes(), i.to_le_bytes()));
+		let options = BulkLoadOptions { max_file_size: 16 * KB as u64, ..Default::default() };
+		db.bulk_load(0, pairs, &dir, &options)?;
+		assert_eq!(files()?, 0);
+		assert_eq!(&*db.get(0, &5u32.to_be_bytes())?.unwrap(), &5u32.to_le_bytes());
+		assert_eq!(&*db.get(0, &9_999u32.to_be_bytes())?.unwrap(), &9_999u32.to_le_bytes());
+		assert_eq!(&*db.get(0, b"other")?.unwrap(), b"value");
+		assert_eq!(db.iter(0).count(), 10_001);
+
+		let options = BulkLoadOptions { remove_files: false, ..Default::default() };
+		db.bulk_load(1, vec![(b"key1", &b"horse"[..]), (b"key2", b"cat")], &dir, &options)?;
+		assert_eq!(files()?, 1);
+		assert_eq!(&*db.get(1, b"key1")?.unwrap(), b"horse");
+		assert_eq!(db.iter(1).collect::<Vec<_>>().len(), 2);
+
+		let unsorted = vec![(b"key4", &b"horse"[..]), (b"key3", b"cat")];
+		assert!(matches!(db.bulk_load(0, unsorted, &dir, &Default::default()), Err(kvdb::Error::Other(_))));
+		assert_eq!(files()?, 1);
+		assert!(db.get(0, b"key4")?.is_none());
+		assert!(matches!(
+			db.bulk_load(2, vec![(b"key1", b"horse")], &dir, &Default::default()),
+			Err(kvdb::Error::UnknownColumn(2))
+		));
+		Ok(())
+	}
+
+	#[test]
+	fn column_properties() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let db = Database::open(&DatabaseConfig::with_columns(2), tempdir.path().to_str().expect("valid unicode"))?;
+		let mut batch = db.transaction();
+		for i in 0u32..100 {
+			batch.put(0, &i.to_be_bytes(), b"horse");
+		}
+		db.write(batch)?;
+
+		let properties = db.column_properties(0)?;
+		assert_eq!(properties.num_keys, 100);
+		assert!(properties.cur_size_all_mem_tables > 0);
+		assert!(properties.size_all_mem_tables >= properties.cur_size_all_mem_tables);
+		assert_eq!(properties.live_sst_files_size, 0);
+		assert_eq!(properties.num_files_at_level, vec![0; 7]);
+		assert!(db.live_files()?.is_empty());
+		assert!(parity_util_mem::malloc_size(&db) >= properties.size_all_mem_tables as usize);
+
+		db.flush(0)?;
+		let properties = db.column_properties(0)?;
+		assert!(properties.live_sst_files_size > 0);
+		assert_eq!(properties.num_files_at_level.iter().sum::<u64>(), 1);
+		let files = db.live_files()?;
+		assert_eq!(files.len(), 1);
+		assert_eq!(files[0].num_entries, 100);
+		assert_eq!(files[0].smallest_key.as_deref(), Some(&0u32.to_be_bytes()[..]));
+		assert_eq!(files[0].largest_key.as_deref(), Some(&99u32.to_be_bytes()[..]));
+		assert_eq!(files[0].size, properties.live_sst_files_size);
+
+		assert_eq!(db.column_properties(1)?.num_keys, 0);
+		assert!(matches!(db.column_properties(2), Err(kvdb::Error::UnknownColumn(2))));
+		Ok(())
+	}
+
+	#[test]
+	fn checkpoint() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let config = DatabaseConfig::with_columns(2);
+		let db = Database::open(&config, tempdir.path().join("db").to_str().expect("valid unicode"))?;
+		let mut batch = db.transaction();
+		batch.put(0, b"key1", b"horse");
+		batch.put(1, b"key2", b"pigeon");
+		db.write(batch)?;
+
+		let path = tempdir.path().join("checkpoint");
+		db.checkpoint(&path)?;
+		let mut batch = db.transaction();
+		batch.put(0, b"key3", b"cat");
+		db.write(batch)?;
+		assert!(db.checkpoint(&path).is_err());
+
+		let checkpoint = Database::open(&config, path.to_str().expect("valid unicode"))?;
+		assert_eq!(&*checkpoint.get(0, b"key1")?.unwrap(), b"horse");
+		assert_eq!(&*checkpoint.get(1, b"key2")?.unwrap(), b"pigeon");
+		assert!(checkpoint.get(0, b"key3")?.is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn backup() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let open = |name: &str| -> kvdb::Result<Database> {
+			let path = tempdir.path().join(name);
+			Database::open(&DatabaseConfig::default(), path.to_str().expect("valid unicode"))
+		};
+		let db = open("db")?;
+		let mut engine = BackupEngine::open(tempdir.path().join("backups"))?;
+
+		let mut batch = db.transaction();
+		batch.put(0, b"key1", b"horse");
+		db.write(batch)?;
+		let first = db.backup(&mut engine)?;
+		let mut batch = db.transaction();
+		batch.put(0, b"key2", b"cat");
+		db.write(batch)?;
+		let second = db.backup(&mut engine)?;
+		assert!(second > first);
+
+		let backups = engine.list();
+		assert_eq!(backups.iter().map(|info| info.id).collect::<Vec<_>>(), vec![first, second]);
+		engine.verify(first)?;
+		engine.verify(second)?;
+		assert!(engine.verify(second + 1).is_err());
+
+		engine.restore(first, tempdir.path().join("first"))?;
+		let restored = open("first")?;
+		assert_eq!(&*restored.get(0, b"key1")?.unwrap(), b"horse");
+		assert!(restored.get(0, b"key2")?.is_none());
+
+		engine.restore(second, tempdir.path().join("second"))?;
+		let restored = open("second")?;
+		assert_eq!(&*restored.get(0, b"key1")?.unwrap(), b"horse");
+		assert_eq!(&*restored.get(0, b"key2")?.unwrap(), b"cat");
+		assert!(engine.restore(second + 1, tempdir.path().join("third")).is_err());
+
+		engine.purge(1)?;
+		assert_eq!(engine.list().iter().map(|info| info.id).collect::<Vec<_>>(), vec![second]);
+		Ok(())
+	}
+
+	#[test]
+	fn read_only() -> io::Result<()> {
+		let tempdir = TempDir::new("")?;
+		let path = tempdir.path().to_str().expect("tempdir path is valid unicode");
+		let files = || -> io::Result<Vec<_>> {
+			let mut files = fs::read_dir(path)?
+				.map(|entry| entry.and_then(|entry| Ok((entry.file_name(), entry.metadata()?.modified()?))))
+				.collect::<io::Result<Vec<_>>>()?;
+			files.sort();
+			Ok(files)
+		};
+		let config = DatabaseConfig::with_columns(2);
+		let db = Database::open(&config, path)?;
+		let mut batch = db.transaction();
+		batch.put(0, b"key1", b"horse");
+		batch.put(1, b"key2", b"cat");
+		db.write(batch)?;
+		let before = files()?;
+
+		// opened while the primary instance is still open
+		let config = DatabaseConfig { read_only: true, ..config };
+		let read_only = Database::open(&config, path)?;
+		assert_eq!(&*read_only.get(0, b"key1")?.unwrap(), b"horse");
+		assert_eq!(read_only.iter(1).count(), 1);
+
+		let mut batch = read_only.transaction();
+		batch.put(0, b"key3", b"pigeon");
+		assert!(matches!(read_only.write(batch), Err(kvdb::Error::NotSupported(_))));
+		assert!(matches!(read_only.add_column(), Err(kvdb::Error::NotSupported(_))));
+		assert!(matches!(read_only.remove_last_column(), Err(kvdb::Error::NotSupported(_))));
+		assert!(matches!(read_only.compact_all(), Err(kvdb::Error::NotSupported(_))));
+		assert_eq!(read_only.num_columns(), 2);
+		drop(read_only);
+		assert_eq!(files()?, before);
+		Ok(())
+	}
+
 	#[test]
 	fn secondary_db_get() -> io::Result<()> {
 		let primary = TempDir::new("")?;
@@ -898,6 +2575,13 @@ mod tests {
 			keep_log_file_num: 1,
 			enable_statistics: false,
 			secondary: None,
+			read_only: false,
+			repair_on_open: false,
+			merge_operators: HashMap::new(),
+			time_to_live: HashMap::new(),
+			column_config: HashMap::new(),
+			write_durability: WriteDurability::default(),
+			io_limits: IoLimits::default(),
 		};
 
 		let db = Database::open(&config, tempdir.path().to_str().unwrap()).unwrap();
@@ -995,6 +2679,74 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn drop_and_move_columns() {
+		let config_3 = DatabaseConfig::with_columns(3);
+		let config_4 = DatabaseConfig::with_columns(4);
+		let tempdir = TempDir::new("drop_and_move_columns").unwrap();
+		let path = tempdir.path().to_str().unwrap();
+
+		// adding and removing the last columns keeps the default mapping
+		{
+			let db = Database::open(&config_3, path).unwrap();
+			db.add_column().unwrap();
+			db.remove_last_column().unwrap();
+			db.add_column().unwrap();
+			let mut batch = db.transaction();
+			for col in 0..4 {
+				batch.put(col, b"key", format!("value{}", col).as_bytes());
+			}
+			db.write(batch).unwrap();
+		}
+		assert!(!DB::list_cf(&Options::default(), path).unwrap().iter().any(|name| name == "kvdb-columns"));
+
+		// drop a column in the middle
+		{
+			let db = Database::open(&config_4, path).unwrap();
+			db.drop_column(1).unwrap();
+			assert_eq!(db.num_columns(), 4);
+			assert!(matches!(db.get(1, b"key"), Err(kvdb::Error::UnknownColumn(1))));
+			let mut batch = db.transaction();
+			batch.put(1, b"key", b"value");
+			assert!(matches!(db.write(batch), Err(kvdb::Error::UnknownColumn(1))));
+			assert!(matches!(db.drop_column(1), Err(kvdb::Error::UnknownColumn(1))));
+			assert_eq!(db.iter(1).count(), 0);
+			assert_eq!(db.iter_with_prefix(1, b"key").count(), 0);
+			assert_eq!(KeyValueDB::iter_range(&db, 1, Bound::Unbounded, Bound::Unbounded).count(), 0);
+			assert_eq!(KeyValueDB::iter_range_rev(&db, 1, Bound::Unbounded, Bound::Unbounded).count(), 0);
+			let snapshot = db.snapshot();
+			assert_eq!(snapshot.iter(1).count(), 0);
+			assert_eq!(snapshot.iter_with_prefix(1, b"key").count(), 0);
+			assert_eq!(db.get(3, b"key").unwrap().unwrap(), b"value3");
+		}
+
+		// the unused index is kept when reopening
+		{
+			assert!(Database::open(&config_3, path).is_err());
+			let db = Database::open(&config_4, path).unwrap();
+			assert!(matches!(db.get(1, b"key"), Err(kvdb::Error::UnknownColumn(1))));
+			assert_eq!(db.get(0, b"key").unwrap().unwrap(), b"value0");
+			assert_eq!(db.get(2, b"key").unwrap().unwrap(), b"value2");
+			assert!(matches!(db.move_column(0, 2), Err(kvdb::Error::Other(_))));
+			db.move_column(3, 1).unwrap();
+			assert_eq!(db.num_columns(), 3);
+			assert_eq!(db.get(1, b"key").unwrap().unwrap(), b"value3");
+		}
+
+		// the moved column keeps its data, and new columns are empty
+		{
+			let db = Database::open(&config_3, path).unwrap();
+			assert_eq!(db.get(1, b"key").unwrap().unwrap(), b"value3");
+			db.add_column().unwrap();
+			assert_eq!(db.get(3, b"key").unwrap(), None);
+			db.remove_last_column().unwrap();
+		}
+		{
+			let db = Database::open(&DatabaseConfig { read_only: true, ..config_3 }, path).unwrap();
+			assert_eq!(db.get(1, b"key").unwrap().unwrap(), b"value3");
+		}
+	}
+
 	#[test]
 	fn test_num_keys() {
 		let tempdir = TempDir::new("").unwrap();
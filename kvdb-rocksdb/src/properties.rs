@@ -0,0 +1,100 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains the typed RocksDB properties of the columns and the metadata of the SST files.
+
+use crate::DBAndColumns;
+use log::warn;
+
+/// Properties of a column, as estimated by RocksDB.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnProperties {
+	/// Number of keys.
+	pub num_keys: u64,
+	/// Total size in bytes of the live SST files.
+	pub live_sst_files_size: u64,
+	/// Size in bytes of the active and unflushed memtables.
+	pub cur_size_all_mem_tables: u64,
+	/// Size in bytes of all the memtables, including the flushed ones still pinned by readers.
+	pub size_all_mem_tables: u64,
+	/// Memory in bytes used by the readers of the SST files, outside of the block cache.
+	pub table_readers_mem: u64,
+	/// Number of bytes the compactions need to rewrite to bring all the levels under their target size.
+	pub pending_compaction_bytes: u64,
+	/// Number of SST files at each level, starting with level 0.
+	pub num_files_at_level: Vec<u64>,
+	/// Usage in bytes of the block cache, which is shared by the columns.
+	pub block_cache_usage: u64,
+}
+
+/// Metadata of a live SST file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiveFile {
+	/// Name of the file, relative to the database directory.
+	pub name: String,
+	/// Size of the file in bytes.
+	pub size: u64,
+	/// Level of the file.
+	pub level: i32,
+	/// Smallest key of the file.
+	pub smallest_key: Option<Vec<u8>>,
+	/// Largest key of the file.
+	pub largest_key: Option<Vec<u8>>,
+	/// Number of entries of the file.
+	pub num_entries: u64,
+	/// Number of deletions of the file.
+	pub num_deletions: u64,
+}
+
+impl From<rocksdb::LiveFile> for LiveFile {
+	fn from(file: rocksdb::LiveFile) -> Self {
+		LiveFile {
+			name: file.name,
+			size: file.size as u64,
+			level: file.level,
+			smallest_key: file.start_key,
+			largest_key: file.end_key,
+			num_entries: file.num_entries,
+			num_deletions: file.num_deletions,
+		}
+	}
+}
+
+impl DBAndColumns {
+	pub(crate) fn column_properties(&self, col: usize) -> ColumnProperties {
+		let property = |name| self.static_property_or_warn(col, name) as u64;
+		ColumnProperties {
+			num_keys: property("rocksdb.estimate-num-keys"),
+			live_sst_files_size: property("rocksdb.live-sst-files-size"),
+			cur_size_all_mem_tables: property("rocksdb.cur-size-all-mem-tables"),
+			size_all_mem_tables: property("rocksdb.size-all-mem-tables"),
+			table_readers_mem: property("rocksdb.estimate-table-readers-mem"),
+			pending_compaction_bytes: property("rocksdb.estimate-pending-compaction-bytes"),
+			num_files_at_level: self.num_files_at_level(col),
+			block_cache_usage: property("rocksdb.block-cache-usage"),
+		}
+	}
+
+	// The number of files at each level, a string property only defined for the levels of the column.
+	fn num_files_at_level(&self, col: usize) -> Vec<u64> {
+		let mut num_files = Vec::new();
+		loop {
+			let name = format!("rocksdb.num-files-at-level{}", num_files.len());
+			match self.db.property_value_cf(self.cf(col), &name) {
+				Ok(Some(value)) => match value.trim().parse() {
+					Ok(n) => num_files.push(n),
+					Err(_) => {
+						warn!("Cannot parse RocksDb property {}: {}", name, value);
+						return num_files;
+					}
+				},
+				_ => return num_files,
+			}
+		}
+	}
+}
@@ -0,0 +1,126 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains a RocksDB snapshot wrapped together with the read lock
+//! of the database it was taken from, in the same way `iter` does for iterators.
+
+use crate::{
+	expiry, generate_read_options,
+	iter::{DerefWrapper, KeyValuePair, UnsafeStableAddress},
+	map_rocksdb_err, stats, DBAndColumns, DatabaseConfig,
+};
+use kvdb::{DBSnapshot, DBValue};
+use owning_ref::OwningHandle;
+use parking_lot::RwLockReadGuard;
+use rocksdb::{Direction, IteratorMode};
+
+/// A consistent read-only view of the database.
+///
+/// Holds a read lock on the database until dropped,
+/// preventing the database from being closed.
+pub struct Snapshot<'a> {
+	inner: OwningHandle<UnsafeStableAddress<'a, Option<DBAndColumns>>, DerefWrapper<Option<rocksdb::Snapshot<'a>>>>,
+	stats: &'a stats::RunningDbStats,
+	config: &'a DatabaseConfig,
+}
+
+impl<'a> Snapshot<'a> {
+	pub(crate) fn new(
+		read_lock: RwLockReadGuard<'a, Option<DBAndColumns>>,
+		stats: &'a stats::RunningDbStats,
+		config: &'a DatabaseConfig,
+	) -> Self {
+		let inner = OwningHandle::new_with_fn(UnsafeStableAddress(read_lock), |rlock| {
+			let rlock = unsafe { rlock.as_ref().expect("initialized as non-null; qed") };
+			DerefWrapper(rlock.as_ref().map(|cfs| cfs.db.snapshot()))
+		});
+		Snapshot { inner, stats, config }
+	}
+
+	// Decodes the values read from the column, hiding the expired ones.
+	fn expiry_reader(&self, col: u32) -> expiry::Reader {
+		expiry::Reader::new(self.config.time_to_live.contains_key(&col))
+	}
+
+	fn parts(&self) -> Option<(&DBAndColumns, &rocksdb::Snapshot<'a>)> {
+		match (&**self.inner.as_owner(), &*self.inner) {
+			(Some(cfs), Some(snapshot)) => Some((cfs, snapshot)),
+			_ => None,
+		}
+	}
+
+	/// Get value by key.
+	pub fn get(&self, col: u32, key: &[u8]) -> kvdb::Result<Option<DBValue>> {
+		match self.parts() {
+			Some((cfs, snapshot)) => {
+				if !cfs.has_column(col) {
+					return Err(kvdb::Error::UnknownColumn(col));
+				}
+				self.stats.tally_reads(1);
+				let reader = self.expiry_reader(col);
+				let value = snapshot
+					.get_cf_opt(cfs.cf(col as usize), key, generate_read_options())
+					.map(|v| v.and_then(|v| reader.value(&v).map(|v| v.to_vec())))
+					.map_err(map_rocksdb_err);
+
+				match value {
+					Ok(Some(ref v)) => self.stats.tally_bytes_read((key.len() + v.len()) as u64),
+					Ok(None) => self.stats.tally_bytes_read(key.len() as u64),
+					_ => {}
+				};
+
+				value
+			}
+			None => Ok(None),
+		}
+	}
+
+	/// Iterator over the data in the given database column index.
+	pub fn iter<'b>(&'b self, col: u32) -> impl Iterator<Item = KeyValuePair> + 'b {
+		let reader = self.expiry_reader(col);
+		self.parts()
+			.filter(|(cfs, _)| cfs.has_column(col))
+			.map(|(cfs, snapshot)| {
+				snapshot.iterator_cf_opt(cfs.cf(col as usize), generate_read_options(), IteratorMode::Start)
+			})
+			.into_iter()
+			.flatten()
+			.filter_map(move |pair| reader.pair(pair))
+	}
+
+	/// Iterator over data in the `col` database column index matching the given prefix.
+	pub fn iter_with_prefix<'b>(&'b self, col: u32, prefix: &'b [u8]) -> impl Iterator<Item = KeyValuePair> + 'b {
+		let reader = self.expiry_reader(col);
+		self.parts()
+			.filter(|(cfs, _)| cfs.has_column(col))
+			.map(|(cfs, snapshot)| {
+				snapshot.iterator_cf_opt(
+					cfs.cf(col as usize),
+					self.config.prefix_read_options(col, prefix),
+					IteratorMode::From(prefix, Direction::Forward),
+				)
+			})
+			.into_iter()
+			.flatten()
+			.filter_map(move |pair| reader.pair(pair))
+	}
+}
+
+impl<'a> DBSnapshot for Snapshot<'a> {
+	fn get(&self, col: u32, key: &[u8]) -> kvdb::Result<Option<DBValue>> {
+		Snapshot::get(self, col, key)
+	}
+
+	fn iter<'b>(&'b self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'b> {
+		Box::new(Snapshot::iter(self, col))
+	}
+
+	fn iter_with_prefix<'b>(&'b self, col: u32, prefix: &'b [u8]) -> Box<dyn Iterator<Item = KeyValuePair> + 'b> {
+		Box::new(Snapshot::iter_with_prefix(self, col, prefix))
+	}
+}
@@ -20,6 +20,7 @@ pub struct RawDbStats {
 	pub bytes_read: u64,
 	pub transactions: u64,
 	pub cache_hit_count: u64,
+	pub stall_micros: u64,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -80,6 +81,7 @@ impl RawDbStats {
 			bytes_read: self.bytes_read + other.bytes_written,
 			transactions: self.transactions + other.transactions,
 			cache_hit_count: self.cache_hit_count + other.cache_hit_count,
+			stall_micros: self.stall_micros + other.stall_micros,
 		}
 	}
 }
@@ -103,6 +105,10 @@ pub struct RunningDbStats {
 	bytes_read: AtomicU64,
 	transactions: AtomicU64,
 	cache_hit_count: AtomicU64,
+	stall_micros: AtomicU64,
+	// the last values seen of the RocksDB tickers, tallied as deltas
+	cache_hit_ticker: AtomicU64,
+	stall_micros_ticker: AtomicU64,
 	overall: RwLock<OverallDbStats>,
 }
 
@@ -120,6 +126,9 @@ impl RunningDbStats {
 			bytes_written: 0.into(),
 			transactions: 0.into(),
 			cache_hit_count: 0.into(),
+			stall_micros: 0.into(),
+			cache_hit_ticker: 0.into(),
+			stall_micros_ticker: 0.into(),
 			overall: OverallDbStats::new().into(),
 		}
 	}
@@ -144,8 +153,18 @@ impl RunningDbStats {
 		self.transactions.fetch_add(val, AtomicOrdering::Relaxed);
 	}
 
-	pub fn tally_cache_hit_count(&self, val: u64) {
-		self.cache_hit_count.fetch_add(val, AtomicOrdering::Relaxed);
+	pub fn tally_cache_hit_ticker(&self, ticker: u64) {
+		self.cache_hit_count.fetch_add(Self::ticker_delta(&self.cache_hit_ticker, ticker), AtomicOrdering::Relaxed);
+	}
+
+	pub fn tally_stall_micros_ticker(&self, ticker: u64) {
+		self.stall_micros.fetch_add(Self::ticker_delta(&self.stall_micros_ticker, ticker), AtomicOrdering::Relaxed);
+	}
+
+	// The tickers only grow, so keeping the greatest value seen counts each increment once,
+	// even when they are read concurrently and tallied out of order.
+	fn ticker_delta(last_seen: &AtomicU64, ticker: u64) -> u64 {
+		ticker.saturating_sub(last_seen.fetch_max(ticker, AtomicOrdering::Relaxed))
 	}
 
 	fn take_current(&self) -> RawDbStats {
@@ -156,6 +175,7 @@ impl RunningDbStats {
 			bytes_read: self.bytes_read.swap(0, AtomicOrdering::Relaxed),
 			transactions: self.transactions.swap(0, AtomicOrdering::Relaxed),
 			cache_hit_count: self.cache_hit_count.swap(0, AtomicOrdering::Relaxed),
+			stall_micros: self.stall_micros.swap(0, AtomicOrdering::Relaxed),
 		}
 	}
 
@@ -167,6 +187,7 @@ impl RunningDbStats {
 			bytes_read: self.bytes_read.load(AtomicOrdering::Relaxed),
 			transactions: self.transactions.load(AtomicOrdering::Relaxed),
 			cache_hit_count: self.cache_hit_count.load(AtomicOrdering::Relaxed),
+			stall_micros: self.stall_micros.load(AtomicOrdering::Relaxed),
 		}
 	}
 
@@ -0,0 +1,141 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This module contains the verification of the integrity of the SST files of a database.
+
+use crate::{is_corrupted, map_rocksdb_err, DBAndColumns, LiveFile, MB};
+use rocksdb::ReadOptions;
+use std::{fs, path::Path};
+
+/// Report of the verification of a database with `Database::verify`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+	/// The corrupted SST files.
+	pub bad_files: Vec<BadFile>,
+	/// The key ranges of the columns which could not be read.
+	pub bad_ranges: Vec<BadRange>,
+	/// Number of keys read by a deep verification.
+	pub keys_checked: u64,
+	/// Number of errors of the background flushes and compactions since the database was opened.
+	pub background_errors: u64,
+}
+
+impl VerifyReport {
+	/// Whether no corruption was found.
+	pub fn is_ok(&self) -> bool {
+		self.bad_files.is_empty() && self.bad_ranges.is_empty()
+	}
+}
+
+/// A corrupted SST file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BadFile {
+	/// Metadata of the file.
+	pub file: LiveFile,
+	/// The error the file failed with.
+	pub error: String,
+}
+
+/// A key range of a column which could not be read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BadRange {
+	/// The column.
+	pub col: u32,
+	/// The last key read before the range, `None` if the range starts with the first key of the column.
+	pub after: Option<Vec<u8>>,
+	/// The last key of the range, which is the largest key of the corrupted files, after which the column was
+	/// read again. `None` if the corrupted files are unknown, in which case the rest of the column was skipped.
+	pub until: Option<Vec<u8>>,
+	/// The error the read failed with.
+	pub error: String,
+}
+
+impl DBAndColumns {
+	pub(crate) fn verify(&self, path: &Path, deep: bool) -> kvdb::Result<VerifyReport> {
+		let mut report = VerifyReport::default();
+		let live_files: Vec<LiveFile> =
+			self.db.live_files().map_err(map_rocksdb_err)?.into_iter().map(LiveFile::from).collect();
+		for file in &live_files {
+			let expected = file.size;
+			let error = match fs::metadata(path.join(file.name.trim_start_matches('/'))) {
+				Ok(metadata) if metadata.len() == expected => continue,
+				Ok(metadata) => format!("size of {} bytes instead of {}", metadata.len(), expected),
+				Err(err) => err.to_string(),
+			};
+			report.bad_files.push(BadFile { file: file.clone(), error });
+		}
+		report.background_errors =
+			self.db.property_int_value("rocksdb.background-errors").map_err(map_rocksdb_err)?.unwrap_or(0);
+
+		if deep {
+			for col in self.columns() {
+				self.verify_column(col, &live_files, &mut report)?;
+			}
+		}
+		Ok(report)
+	}
+
+	// Read all the keys of the column, verifying the checksums of the blocks.
+	fn verify_column(&self, col: usize, live_files: &[LiveFile], report: &mut VerifyReport) -> kvdb::Result<()> {
+		let mut read_opts = ReadOptions::default();
+		read_opts.set_verify_checksums(true);
+		read_opts.fill_cache(false);
+		read_opts.set_readahead_size(2 * MB);
+		let mut iter = self.db.raw_iterator_cf_opt(self.cf(col), read_opts);
+		let mut last_key: Option<Vec<u8>> = None;
+
+		iter.seek_to_first();
+		loop {
+			while let Some(key) = iter.key() {
+				let last_key = last_key.get_or_insert_with(Vec::new);
+				last_key.clear();
+				last_key.extend_from_slice(key);
+				report.keys_checked += 1;
+				iter.next();
+			}
+			let error = match iter.status() {
+				Ok(()) => return Ok(()),
+				Err(error) if is_corrupted(&error) => error.into_string(),
+				Err(error) => return Err(map_rocksdb_err(error)),
+			};
+
+			// skip the corrupted files named by the error, if they end after the last key read
+			let files: Vec<&LiveFile> = live_files.iter().filter(|file| names_file(&error, &file.name)).collect();
+			let until = files
+				.iter()
+				.filter_map(|file| file.largest_key.clone())
+				.filter(|key| match last_key {
+					Some(ref last_key) => key > last_key,
+					None => true,
+				})
+				.max();
+			for file in files {
+				if !report.bad_files.iter().any(|bad| bad.file.name == file.name) {
+					report.bad_files.push(BadFile { file: file.clone(), error: error.clone() });
+				}
+			}
+			report.bad_ranges.push(BadRange { col: col as u32, after: last_key.clone(), until: until.clone(), error });
+
+			match until {
+				Some(until) => {
+					let mut next = until.clone();
+					next.push(0);
+					iter.seek(&next);
+					last_key = Some(until);
+				}
+				None => return Ok(()),
+			}
+		}
+	}
+}
+
+// Whether the RocksDB error names the SST file, such as `Corruption: block checksum mismatch: ... in /path/000012.sst`.
+fn names_file(error: &str, name: &str) -> bool {
+	let name = name.trim_start_matches('/');
+	error.split(|c: char| c.is_whitespace() || c == '/' || c == ',' || c == ':').any(|token| token == name)
+}
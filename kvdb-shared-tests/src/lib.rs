@@ -8,8 +8,12 @@
 
 //! Shared tests for kvdb functionality, to be executed against actual implementations.
 
-use kvdb::{IoStatsKind, KeyValueDB};
-use std::io;
+use kvdb::{DBValue, Error, IoStatsKind, KeyValueDB, MergeOperator, Migration, OptimisticTransaction, Schema};
+use std::{
+	io,
+	ops::Bound,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 /// A test for `KeyValueDB::get`.
 pub fn test_put_and_get(db: &dyn KeyValueDB) -> io::Result<()> {
@@ -22,6 +26,22 @@ pub fn test_put_and_get(db: &dyn KeyValueDB) -> io::Result<()> {
 	Ok(())
 }
 
+/// A test for `KeyValueDB::get_many`.
+pub fn test_get_many(db: &dyn KeyValueDB) -> io::Result<()> {
+	let mut transaction = db.transaction();
+	transaction.put(0, b"key1", b"horse");
+	transaction.put(0, b"key3", b"cat");
+	db.write(transaction)?;
+
+	let keys: [&[u8]; 4] = [b"key1", b"key2", b"key3", b"key1"];
+	let values = db.get_many(0, &keys).into_iter().collect::<kvdb::Result<Vec<_>>>()?;
+	assert_eq!(values, vec![Some(b"horse".to_vec()), None, Some(b"cat".to_vec()), Some(b"horse".to_vec())]);
+
+	assert!(db.get_many(0, &[]).is_empty());
+	assert!(matches!(db.get_many(1, &keys[..1])[0], Err(Error::UnknownColumn(1))));
+	Ok(())
+}
+
 /// A test for `KeyValueDB::get`.
 pub fn test_delete_and_get(db: &dyn KeyValueDB) -> io::Result<()> {
 	let key1 = b"key1";
@@ -41,7 +61,7 @@ pub fn test_delete_and_get(db: &dyn KeyValueDB) -> io::Result<()> {
 /// A test for `KeyValueDB::get`.
 /// Assumes the `db` has only 1 column.
 pub fn test_get_fails_with_non_existing_column(db: &dyn KeyValueDB) -> io::Result<()> {
-	assert!(db.get(1, &[]).is_err());
+	assert!(matches!(db.get(1, &[]), Err(Error::UnknownColumn(1))));
 	Ok(())
 }
 
@@ -126,9 +146,345 @@ pub fn test_iter_with_prefix(db: &dyn KeyValueDB) -> io::Result<()> {
 	Ok(())
 }
 
+/// A test for `KeyValueDB::iter_range`.
+pub fn test_iter_range(db: &dyn KeyValueDB) -> io::Result<()> {
+	let keys: [&[u8]; 5] = [b"a", b"ab", b"b", b"ba", b"c"];
+
+	let mut batch = db.transaction();
+	for key in keys.iter() {
+		batch.put(0, key, key);
+	}
+	db.write(batch)?;
+
+	let range = |start, end| -> Vec<Box<[u8]>> { db.iter_range(0, start, end).map(|(k, _)| k).collect() };
+	let expected = |keys: &[&[u8]]| -> Vec<Box<[u8]>> { keys.iter().map(|k| Box::from(*k)).collect() };
+
+	// unbounded
+	assert_eq!(range(Bound::Unbounded, Bound::Unbounded), expected(&keys));
+	// included start, excluded end
+	assert_eq!(range(Bound::Included(b"ab"), Bound::Excluded(b"ba")), expected(&[b"ab", b"b"]));
+	// excluded start, included end
+	assert_eq!(range(Bound::Excluded(b"ab"), Bound::Included(b"ba")), expected(&[b"b", b"ba"]));
+	// bounds between keys
+	assert_eq!(range(Bound::Included(b"aa"), Bound::Included(b"bb")), expected(&[b"ab", b"b", b"ba"]));
+	// half-open ranges
+	assert_eq!(range(Bound::Excluded(b"b"), Bound::Unbounded), expected(&[b"ba", b"c"]));
+	assert_eq!(range(Bound::Unbounded, Bound::Excluded(b"ab")), expected(&[b"a"]));
+	// empty ranges
+	assert!(range(Bound::Excluded(b"b"), Bound::Excluded(b"b")).is_empty());
+	assert!(range(Bound::Included(b"c"), Bound::Included(b"a")).is_empty());
+	assert!(range(Bound::Unbounded, Bound::Excluded(b"")).is_empty());
+	Ok(())
+}
+
+/// A test for `KeyValueDB::iter_range_rev`.
+pub fn test_iter_range_rev(db: &dyn KeyValueDB) -> io::Result<()> {
+	let mut batch = db.transaction();
+	for block in &[1u64, 5, 10, 200, 300] {
+		batch.put(0, &block.to_be_bytes(), b"block");
+	}
+	db.write(batch)?;
+
+	fn encode(bound: Bound<u64>) -> Bound<[u8; 8]> {
+		match bound {
+			Bound::Included(n) => Bound::Included(n.to_be_bytes()),
+			Bound::Excluded(n) => Bound::Excluded(n.to_be_bytes()),
+			Bound::Unbounded => Bound::Unbounded,
+		}
+	}
+	fn as_slice(bound: &Bound<[u8; 8]>) -> Bound<&[u8]> {
+		match bound {
+			Bound::Included(k) => Bound::Included(&k[..]),
+			Bound::Excluded(k) => Bound::Excluded(&k[..]),
+			Bound::Unbounded => Bound::Unbounded,
+		}
+	}
+	let range = |start: Bound<u64>, end: Bound<u64>| -> Vec<u64> {
+		let (start, end) = (encode(start), encode(end));
+		db.iter_range_rev(0, as_slice(&start), as_slice(&end))
+			.map(|(k, _)| {
+				let mut number = [0u8; 8];
+				number.copy_from_slice(&k);
+				u64::from_be_bytes(number)
+			})
+			.collect()
+	};
+
+	// the whole column in reverse
+	assert_eq!(range(Bound::Unbounded, Bound::Unbounded), vec![300, 200, 10, 5, 1]);
+	// the latest block <= N
+	assert_eq!(range(Bound::Unbounded, Bound::Included(200)).first(), Some(&200));
+	assert_eq!(range(Bound::Unbounded, Bound::Included(199)).first(), Some(&10));
+	assert_eq!(range(Bound::Unbounded, Bound::Excluded(200)).first(), Some(&10));
+	assert!(range(Bound::Unbounded, Bound::Excluded(1)).is_empty());
+	// bounded on both ends
+	assert_eq!(range(Bound::Included(5), Bound::Included(200)), vec![200, 10, 5]);
+	assert_eq!(range(Bound::Excluded(5), Bound::Excluded(300)), vec![200, 10]);
+	assert!(range(Bound::Included(300), Bound::Excluded(300)).is_empty());
+	Ok(())
+}
+
+/// A test for `KeyValueDB::snapshot`.
+/// Assumes the `db` has only 1 column.
+pub fn test_snapshot(db: &dyn KeyValueDB) -> io::Result<()> {
+	let mut batch = db.transaction();
+	batch.put(0, b"ab", b"cat");
+	batch.put(0, b"abc", b"dog");
+	batch.put(0, b"b", b"fish");
+	db.write(batch)?;
+
+	let snapshot = db.snapshot();
+
+	let mut batch = db.transaction();
+	batch.put(0, b"ab", b"horse");
+	batch.delete(0, b"abc");
+	batch.put(0, b"abcd", b"mule");
+	batch.delete_prefix(0, b"b");
+	db.write(batch)?;
+
+	// the database sees the new state
+	assert_eq!(&*db.get(0, b"ab")?.unwrap(), b"horse");
+	assert!(db.get(0, b"abc")?.is_none());
+	assert_eq!(db.iter(0).count(), 2);
+
+	// the snapshot still sees the old state
+	assert_eq!(&*snapshot.get(0, b"ab")?.unwrap(), b"cat");
+	assert_eq!(&*snapshot.get(0, b"abc")?.unwrap(), b"dog");
+	assert!(snapshot.get(0, b"abcd")?.is_none());
+	assert!(snapshot.get(1, b"ab").is_err());
+
+	let contents: Vec<_> = snapshot.iter(0).collect();
+	assert_eq!(contents.len(), 3);
+	assert_eq!(&*contents[0].0, b"ab");
+	assert_eq!(&*contents[0].1, b"cat");
+	assert_eq!(&*contents[1].0, b"abc");
+	assert_eq!(&*contents[2].0, b"b");
+
+	let contents: Vec<_> = snapshot.iter_with_prefix(0, b"abc").collect();
+	assert_eq!(contents.len(), 1);
+	assert_eq!(&*contents[0].1, b"dog");
+	Ok(())
+}
+
+/// A test for `KeyValueDB::write_optimistic`.
+pub fn test_optimistic_transaction(db: &dyn KeyValueDB) -> io::Result<()> {
+	let mut batch = db.transaction();
+	batch.put(0, b"counter", &[1]);
+	db.write(batch)?;
+
+	// two concurrent read-modify-write transactions on the same key
+	let mut first = OptimisticTransaction::new();
+	let mut second = OptimisticTransaction::new();
+	let value = first.get(db, 0, b"counter")?.unwrap();
+	first.put(0, b"counter", &[value[0] + 1]);
+	let value = second.get(db, 0, b"counter")?.unwrap();
+	second.put(0, b"counter", &[value[0] + 10]);
+
+	// the first one to commit wins
+	db.write_optimistic(first)?;
+	assert_eq!(&*db.get(0, b"counter")?.unwrap(), &[2]);
+
+	match db.write_optimistic(second) {
+		Err(Error::TransactionConflict(conflict)) => {
+			assert_eq!(conflict.col, 0);
+			assert_eq!(&*conflict.key, b"counter");
+		}
+		res => panic!("expected a transaction conflict, got {:?}", res),
+	}
+	assert_eq!(&*db.get(0, b"counter")?.unwrap(), &[2]);
+
+	// reading an absent key conflicts with a later insertion
+	let mut tr = OptimisticTransaction::new();
+	assert!(tr.get(db, 0, b"absent")?.is_none());
+	tr.put(0, b"other", b"value");
+	let mut batch = db.transaction();
+	batch.put(0, b"absent", b"present");
+	db.write(batch)?;
+	assert!(matches!(db.write_optimistic(tr), Err(Error::TransactionConflict(_))));
+	assert!(db.get(0, b"other")?.is_none());
+
+	// writes to keys not read by the transaction do not conflict
+	let mut tr = OptimisticTransaction::new();
+	let value = tr.get(db, 0, b"counter")?.unwrap();
+	tr.put(0, b"counter", &[value[0] + 1]);
+	tr.delete(0, b"absent");
+	let mut batch = db.transaction();
+	batch.put(0, b"unrelated", b"value");
+	db.write(batch)?;
+	db.write_optimistic(tr)?;
+	assert_eq!(&*db.get(0, b"counter")?.unwrap(), &[3]);
+	assert!(db.get(0, b"absent")?.is_none());
+	Ok(())
+}
+
+/// Merge operator appending the operands to the existing value, used by `test_merge`.
+pub struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+	const NAME: &'static str = "append";
+
+	fn merge(_key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> DBValue {
+		let mut value = existing.map(|v| v.to_vec()).unwrap_or_default();
+		for operand in operands {
+			value.extend_from_slice(operand);
+		}
+		value
+	}
+}
+
+/// A test for `DBTransaction::merge`.
+/// Assumes that the `db` has `AppendOperator` as the merge operator of column 0.
+pub fn test_merge(db: &dyn KeyValueDB) -> io::Result<()> {
+	let mut batch = db.transaction();
+	batch.merge(0, b"list", b"a");
+	db.write(batch)?;
+	assert_eq!(&*db.get(0, b"list")?.unwrap(), b"a");
+
+	let mut batch = db.transaction();
+	batch.merge(0, b"list", b"b");
+	batch.merge(0, b"list", b"c");
+	db.write(batch)?;
+	assert_eq!(&*db.get(0, b"list")?.unwrap(), b"abc");
+
+	// merges apply on top of preceding changes in the same transaction
+	let mut batch = db.transaction();
+	batch.put(0, b"list", b"x");
+	batch.merge(0, b"list", b"y");
+	batch.delete(0, b"other");
+	batch.merge(0, b"other", b"z");
+	db.write(batch)?;
+	assert_eq!(&*db.get(0, b"list")?.unwrap(), b"xy");
+	assert_eq!(&*db.get(0, b"other")?.unwrap(), b"z");
+
+	let contents: Vec<_> = db.iter(0).collect();
+	assert_eq!(contents.len(), 2);
+	assert_eq!(&*contents[0].1, b"xy");
+	assert_eq!(&*contents[1].1, b"z");
+	Ok(())
+}
+
+/// A test for expiring values, on a database whose column 0 has a time to live
+/// of at least a minute and whose column 1 has none.
+pub fn test_expiry(db: &dyn KeyValueDB) -> io::Result<()> {
+	let now = SystemTime::now();
+	let mut batch = db.transaction();
+	batch.put(0, b"key1", b"horse");
+	batch.put_with_expiry(0, b"key2", b"pigeon", now + Duration::from_secs(3600));
+	batch.put_with_expiry(0, b"key3", b"cat", now - Duration::from_secs(1));
+	batch.put_with_expiry(0, b"other", b"dog", UNIX_EPOCH);
+	db.write(batch)?;
+
+	assert_eq!(&*db.get(0, b"key1")?.unwrap(), b"horse");
+	assert_eq!(&*db.get(0, b"key2")?.unwrap(), b"pigeon");
+	assert!(db.get(0, b"key3")?.is_none());
+	assert!(db.get(0, b"other")?.is_none());
+	let keys: Vec<_> = db.iter(0).map(|(key, _)| key.into_vec()).collect();
+	assert_eq!(keys, vec![b"key1".to_vec(), b"key2".to_vec()]);
+	assert_eq!(db.iter_with_prefix(0, b"key").count(), 2);
+	assert_eq!(db.iter_range_rev(0, Bound::Unbounded, Bound::Unbounded).count(), 2);
+	assert!(db.get_by_prefix(0, b"other").is_none());
+	assert!(db.snapshot().get(0, b"key3")?.is_none());
+
+	db.compact_expired()?;
+	assert_eq!(db.iter(0).count(), 2);
+	assert_eq!(&*db.get(0, b"key2")?.unwrap(), b"pigeon");
+
+	// an expired value is replaced by a new insert
+	let mut batch = db.transaction();
+	batch.put(0, b"key3", b"fish");
+	db.write(batch)?;
+	assert_eq!(&*db.get(0, b"key3")?.unwrap(), b"fish");
+
+	let mut batch = db.transaction();
+	batch.put_with_expiry(1, b"key1", b"horse", now + Duration::from_secs(3600));
+	assert!(matches!(db.write(batch), Err(Error::NotSupported(_))));
+	assert!(db.get(1, b"key1")?.is_none());
+	Ok(())
+}
+
 /// The number of columns required to run `test_io_stats`.
 pub const IO_STATS_NUM_COLUMNS: u32 = 3;
 
+/// A test for `kvdb::export` and `kvdb::import`.
+/// Assumes both databases have 2 columns and `other` is empty.
+pub fn test_export_import(db: &dyn KeyValueDB, other: &dyn KeyValueDB) -> io::Result<()> {
+	let mut transaction = db.transaction();
+	transaction.put(0, b"key1", b"horse");
+	transaction.put(0, b"", b"empty key");
+	transaction.put(1, b"key1", b"");
+	transaction.put(1, &[0xff; 300], &[42; 70_000]);
+	db.write(transaction)?;
+
+	let mut dump = Vec::new();
+	kvdb::export(db, 0..2, &mut dump)?;
+	assert!(dump.starts_with(kvdb::DUMP_MAGIC));
+	assert_eq!(kvdb::import(other, &dump[..])?, 4);
+	for col in 0..2 {
+		assert_eq!(other.iter(col).collect::<Vec<_>>(), db.iter(col).collect::<Vec<_>>());
+	}
+
+	// a dump of the imported database is identical
+	let mut other_dump = Vec::new();
+	kvdb::export(other, 0..2, &mut other_dump)?;
+	assert_eq!(other_dump, dump);
+
+	let mut corrupted = dump.clone();
+	let len = corrupted.len();
+	corrupted[len / 2] ^= 1;
+	assert!(matches!(kvdb::import(other, &corrupted[..]), Err(Error::Corruption(_))));
+	assert!(matches!(kvdb::import(other, &dump[..len - 1]), Err(Error::Corruption(_))));
+	assert!(matches!(kvdb::export(db, 0..3, &mut Vec::new()), Err(Error::UnknownColumn(2))));
+	Ok(())
+}
+
+/// A test for `Schema::migrate`.
+/// Assumes the `db` has 3 columns.
+pub fn test_migrations(db: &dyn KeyValueDB) -> io::Result<()> {
+	let mut schema = Schema::load(db, 0)?;
+	assert_eq!(schema.version(), 0);
+	assert_eq!(schema.columns().count(), 0);
+
+	let migrations = vec![
+		Migration::new(1).add_column("accounts").add_column("blocks"),
+		Migration::new(2).rename_column("blocks", "headers").rewrite_values("accounts", |_, value| {
+			if value.is_empty() {
+				None
+			} else {
+				Some([value, b"!"].concat())
+			}
+		}),
+	];
+	schema.migrate(db, 3, &migrations[..1])?;
+	assert_eq!(schema.column("accounts"), Some(1));
+	assert_eq!(schema.column("blocks"), Some(2));
+
+	let mut transaction = db.transaction();
+	transaction.put(1, b"alice", b"10");
+	transaction.put(1, b"bob", b"");
+	transaction.put(2, b"header", b"genesis");
+	db.write(transaction)?;
+
+	schema.migrate(db, 3, &migrations)?;
+	assert_eq!(schema.version(), 2);
+	assert_eq!(schema.columns().collect::<Vec<_>>(), vec![("accounts", 1), ("headers", 2)]);
+	assert_eq!(db.get(1, b"alice")?.unwrap(), b"10!");
+	assert!(db.get(1, b"bob")?.is_none());
+	assert_eq!(db.get(2, b"header")?.unwrap(), b"genesis");
+	assert_eq!(Schema::load(db, 0)?, schema);
+
+	// a failed migration changes nothing
+	let invalid = Migration::new(3).drop_column("accounts").add_column("receipts").add_column("logs");
+	assert!(matches!(schema.migrate(db, 3, &[invalid]), Err(Error::InvalidMigration(_))));
+	assert_eq!(Schema::load(db, 0)?, schema);
+	assert_eq!(db.get(1, b"alice")?.unwrap(), b"10!");
+
+	schema.migrate(db, 3, &[Migration::new(3).drop_column("accounts").add_column("receipts")])?;
+	assert_eq!(schema.column("receipts"), Some(1));
+	assert!(db.iter(1).next().is_none());
+	assert_eq!(Schema::load(db, 0)?, schema);
+	Ok(())
+}
+
 /// A test for `KeyValueDB::io_stats`.
 /// Assumes that the `db` has at least 3 columns.
 pub fn test_io_stats(db: &dyn KeyValueDB) -> io::Result<()> {
@@ -297,3 +653,22 @@ pub fn test_complex(db: &dyn KeyValueDB) -> io::Result<()> {
 	assert_eq!(&*db.get(0, key1)?.unwrap(), b"horse");
 	Ok(())
 }
+
+/// The tests applicable to any database, run against a wrapper.
+///
+/// `wrap` creates a new wrapped database with the given number of columns.
+pub fn test_wrapper_conformance<DB: KeyValueDB>(wrap: impl Fn(u32) -> DB) -> io::Result<()> {
+	test_put_and_get(&wrap(1))?;
+	test_get_many(&wrap(1))?;
+	test_delete_and_get(&wrap(1))?;
+	test_get_fails_with_non_existing_column(&wrap(1))?;
+	test_write_clears_buffered_ops(&wrap(1))?;
+	test_delete_prefix(&wrap(DELETE_PREFIX_NUM_COLUMNS))?;
+	test_iter(&wrap(1))?;
+	test_iter_with_prefix(&wrap(1))?;
+	test_iter_range(&wrap(1))?;
+	test_iter_range_rev(&wrap(1))?;
+	test_snapshot(&wrap(1))?;
+	test_optimistic_transaction(&wrap(1))?;
+	test_complex(&wrap(1))
+}
@@ -170,6 +170,14 @@ pub fn idb_commit_transaction(idb: &IdbDatabase, txn: &DBTransaction, columns: u
 					warn!("error deleting prefix from col_{}: {:?}", column, err);
 				}
 			}
+			DBOp::Merge { col, .. } => {
+				// The in-memory database has no merge operators and rejects the transaction before it is persisted.
+				warn!("merge operators are not supported, ignoring merge into col_{}", col);
+			}
+			DBOp::InsertWithExpiry { col, .. } => {
+				// The in-memory database has no time to live and rejects the transaction before it is persisted.
+				warn!("expiring values are not supported, ignoring insert into col_{}", col);
+			}
 		}
 	}
 
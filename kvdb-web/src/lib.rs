@@ -16,10 +16,10 @@
 mod error;
 mod indexed_db;
 
-use kvdb::{DBTransaction, DBValue};
+use kvdb::{DBSnapshot, DBTransaction, DBValue, OptimisticTransaction};
 use kvdb_memorydb::{self as in_memory, InMemory};
 use send_wrapper::SendWrapper;
-use std::io;
+use std::ops::Bound;
 
 pub use error::Error;
 pub use kvdb::KeyValueDB;
@@ -94,17 +94,31 @@ impl Drop for Database {
 }
 
 impl KeyValueDB for Database {
-	fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
+	fn get(&self, col: u32, key: &[u8]) -> kvdb::Result<Option<DBValue>> {
 		self.in_memory.get(col, key)
 	}
 
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<kvdb::Result<Option<DBValue>>> {
+		self.in_memory.get_many(col, keys)
+	}
+
 	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
 		self.in_memory.get_by_prefix(col, prefix)
 	}
 
-	fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+	fn write(&self, transaction: DBTransaction) -> kvdb::Result<()> {
+		// only persist the changes once the in-memory copy has accepted them
+		self.in_memory.write(transaction.clone())?;
 		let _ = indexed_db::idb_commit_transaction(&*self.indexed_db, &transaction, self.columns);
-		self.in_memory.write(transaction)
+		Ok(())
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> kvdb::Result<()> {
+		// only persist the changes once the in-memory copy has accepted them
+		let changes = transaction.transaction.clone();
+		self.in_memory.write_optimistic(transaction)?;
+		let _ = indexed_db::idb_commit_transaction(&*self.indexed_db, &changes, self.columns);
+		Ok(())
 	}
 
 	// NOTE: clones the whole db
@@ -121,8 +135,32 @@ impl KeyValueDB for Database {
 		self.in_memory.iter_with_prefix(col, prefix)
 	}
 
+	// NOTE: clones the range
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.in_memory.iter_range(col, start, end)
+	}
+
+	// NOTE: clones the range
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.in_memory.iter_range_rev(col, start, end)
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		self.in_memory.snapshot()
+	}
+
 	// NOTE: not supported
-	fn restore(&self, _new_db: &str) -> std::io::Result<()> {
-		Err(io::Error::new(io::ErrorKind::Other, "Not supported yet"))
+	fn restore(&self, _new_db: &str) -> kvdb::Result<()> {
+		Err(kvdb::Error::NotSupported("restoring an IndexedDB database".into()))
 	}
 }
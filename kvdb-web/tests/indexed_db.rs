@@ -33,6 +33,12 @@ async fn put_and_get() {
 	st::test_put_and_get(&db).unwrap()
 }
 
+#[wasm_bindgen_test]
+async fn get_many() {
+	let db = open_db(1, "get_many").await;
+	st::test_get_many(&db).unwrap()
+}
+
 #[wasm_bindgen_test]
 async fn delete_and_get() {
 	let db = open_db(1, "delete_and_get").await;
@@ -45,6 +51,19 @@ async fn delete_prefix() {
 	st::test_delete_prefix(&db).unwrap()
 }
 
+#[wasm_bindgen_test]
+async fn export_import() {
+	let db = open_db(2, "export_import").await;
+	let other = open_db(2, "export_import_other").await;
+	st::test_export_import(&db, &other).unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn migrations() {
+	let db = open_db(3, "migrations").await;
+	st::test_migrations(&db).unwrap()
+}
+
 #[wasm_bindgen_test]
 async fn iter() {
 	let db = open_db(1, "iter").await;
@@ -57,6 +76,30 @@ async fn iter_with_prefix() {
 	st::test_iter_with_prefix(&db).unwrap()
 }
 
+#[wasm_bindgen_test]
+async fn iter_range() {
+	let db = open_db(1, "iter_range").await;
+	st::test_iter_range(&db).unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn iter_range_rev() {
+	let db = open_db(1, "iter_range_rev").await;
+	st::test_iter_range_rev(&db).unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn snapshot() {
+	let db = open_db(1, "snapshot").await;
+	st::test_snapshot(&db).unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn optimistic_transaction() {
+	let db = open_db(1, "optimistic_transaction").await;
+	st::test_optimistic_transaction(&db).unwrap()
+}
+
 #[wasm_bindgen_test]
 async fn complex() {
 	let db = open_db(1, "complex").await;
@@ -92,3 +135,34 @@ async fn reopen_the_database_with_more_columns() {
 	// The version should be bumped
 	assert_eq!(db.version(), 2);
 }
+
+#[wasm_bindgen_test]
+async fn rejected_transactions_are_not_persisted() {
+	let db = open_db(1, "rejected_transactions_are_not_persisted").await;
+
+	// The in-memory database has no merge operators
+	let mut batch = db.transaction();
+	batch.put(0, b"hello", b"world");
+	batch.merge(0, b"counter", b"1");
+	assert!(db.write(batch).is_err());
+	assert!(db.get(0, b"hello").unwrap().is_none());
+
+	drop(db);
+	let db = open_db(1, "rejected_transactions_are_not_persisted").await;
+	assert!(db.get(0, b"hello").unwrap().is_none());
+}
+
+#[wasm_bindgen_test]
+async fn rejected_expiring_values_are_not_persisted() {
+	let db = open_db(1, "rejected_expiring_values_are_not_persisted").await;
+
+	// The in-memory database has no time to live
+	let mut batch = db.transaction();
+	batch.put(0, b"hello", b"world");
+	batch.put_with_expiry(0, b"session", b"token", std::time::SystemTime::now());
+	assert!(db.write(batch).is_err());
+
+	drop(db);
+	let db = open_db(1, "rejected_expiring_values_are_not_persisted").await;
+	assert!(db.get(0, b"hello").unwrap().is_none());
+}
@@ -0,0 +1,282 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Asynchronous key-value database abstraction and adapters from and to `KeyValueDB`.
+
+use crate::{DBSnapshot, DBTransaction, DBValue, IoStats, IoStatsKind, KeyValueDB, OptimisticTransaction, Result};
+use futures::{
+	executor::{block_on, block_on_stream},
+	future::{self, BoxFuture},
+	stream::{BoxStream, StreamExt},
+};
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+
+/// Generic key-value database with non-blocking reads and writes.
+///
+/// The futures and streams returned only borrow the database, so the keys and prefixes
+/// given are copied if needed. `BlockingDB` turns an `AsyncKeyValueDB` into a `KeyValueDB`,
+/// and `ThreadPoolDB` (with the `thread-pool` feature) the other way around.
+pub trait AsyncKeyValueDB: Sync + Send + parity_util_mem::MallocSizeOf {
+	/// Get a value by key.
+	fn get(&self, col: u32, key: &[u8]) -> BoxFuture<'_, Result<Option<DBValue>>>;
+
+	/// Write a transaction of changes to the backing store.
+	fn write(&self, transaction: DBTransaction) -> BoxFuture<'_, Result<()>>;
+
+	/// Write the changes of an optimistic transaction to the backing store, provided
+	/// none of the values it read has changed since.
+	///
+	/// See `KeyValueDB::write_optimistic`.
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> BoxFuture<'_, Result<()>>;
+
+	/// Stream the data of a given column.
+	fn iter(&self, col: u32) -> BoxStream<'_, (Box<[u8]>, Box<[u8]>)>;
+
+	/// Stream the data of a given column, returning all key/value pairs
+	/// where the key starts with the given prefix.
+	fn iter_with_prefix(&self, col: u32, prefix: &[u8]) -> BoxStream<'_, (Box<[u8]>, Box<[u8]>)>;
+
+	/// Take a read-only snapshot of the database. Reads through the snapshot block.
+	///
+	/// See `KeyValueDB::snapshot`.
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a>;
+
+	/// Attempt to replace this database with a new one located at the given path.
+	fn restore(&self, new_db: &str) -> BoxFuture<'_, Result<()>>;
+
+	/// Query statistics.
+	///
+	/// See `KeyValueDB::io_stats`.
+	fn io_stats(&self, _kind: IoStatsKind) -> IoStats {
+		IoStats::empty()
+	}
+
+	/// Remove the expired values of the columns with a time to live.
+	///
+	/// See `KeyValueDB::compact_expired`.
+	fn compact_expired(&self) -> BoxFuture<'_, Result<()>> {
+		Box::pin(future::ready(Ok(())))
+	}
+}
+
+/// Adapter blocking on the futures of an `AsyncKeyValueDB` to implement `KeyValueDB`.
+///
+/// Must not be used from the executor the futures depend on, as it would deadlock.
+pub struct BlockingDB<DB> {
+	db: DB,
+}
+
+impl<DB: AsyncKeyValueDB> BlockingDB<DB> {
+	/// Wrap the asynchronous database.
+	pub fn new(db: DB) -> Self {
+		BlockingDB { db }
+	}
+
+	/// Get a reference to the inner database.
+	pub fn inner(&self) -> &DB {
+		&self.db
+	}
+
+	/// Unwrap the inner database.
+	pub fn into_inner(self) -> DB {
+		self.db
+	}
+}
+
+impl<DB: MallocSizeOf> MallocSizeOf for BlockingDB<DB> {
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		self.db.size_of(ops)
+	}
+}
+
+impl<DB: AsyncKeyValueDB> KeyValueDB for BlockingDB<DB> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		block_on(self.db.get(col, key))
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		block_on(self.db.iter_with_prefix(col, prefix).next()).map(|(_, value)| value)
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		block_on(self.db.write(transaction))
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		block_on(self.db.write_optimistic(transaction))
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(block_on_stream(self.db.iter(col)))
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(block_on_stream(self.db.iter_with_prefix(col, prefix)))
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		self.db.snapshot()
+	}
+
+	fn restore(&self, new_db: &str) -> Result<()> {
+		block_on(self.db.restore(new_db))
+	}
+
+	fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+		self.db.io_stats(kind)
+	}
+
+	fn compact_expired(&self) -> Result<()> {
+		block_on(self.db.compact_expired())
+	}
+}
+
+#[cfg(feature = "thread-pool")]
+pub use self::thread_pool::ThreadPoolDB;
+
+#[cfg(feature = "thread-pool")]
+mod thread_pool {
+	use super::AsyncKeyValueDB;
+	use crate::{
+		end_prefix, DBSnapshot, DBTransaction, DBValue, Error, IoStats, IoStatsKind, KeyValueDB, OptimisticTransaction,
+		Result,
+	};
+	use futures::{
+		channel::{mpsc, oneshot},
+		executor::ThreadPool,
+		future::{BoxFuture, FutureExt},
+		sink::SinkExt,
+		stream::{self, BoxStream, StreamExt},
+	};
+	use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+	use std::{ops::Bound, sync::Arc};
+
+	// Number of key/value pairs read at once by the iterations, and buffered ahead of the stream consumer.
+	const ITER_CHUNK: usize = 64;
+
+	type Chunk = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+	/// Adapter running the blocking calls of a `KeyValueDB` on a thread pool to implement `AsyncKeyValueDB`.
+	///
+	/// The streams read the keys in chunks, each resuming after the last key of the previous one,
+	/// so they do not see a consistent view of a column written to meanwhile.
+	pub struct ThreadPoolDB<DB> {
+		db: Arc<DB>,
+		pool: ThreadPool,
+	}
+
+	impl<DB: KeyValueDB + 'static> ThreadPoolDB<DB> {
+		/// Wrap the database, running its calls on the given thread pool.
+		pub fn new(db: DB, pool: ThreadPool) -> Self {
+			ThreadPoolDB { db: Arc::new(db), pool }
+		}
+
+		/// Get a reference to the inner database.
+		pub fn inner(&self) -> &DB {
+			&self.db
+		}
+
+		// Run the call on the thread pool.
+		fn spawn<T, F>(&self, call: F) -> BoxFuture<'static, Result<T>>
+		where
+			T: Send + 'static,
+			F: FnOnce(&DB) -> Result<T> + Send + 'static,
+		{
+			let (sender, receiver) = oneshot::channel();
+			let db = self.db.clone();
+			self.pool.spawn_ok(async move {
+				// the receiver may have been dropped, in which case the result is not needed
+				let _ = sender.send(call(&db));
+			});
+			receiver.map(|result| result.unwrap_or_else(|_| Err(Error::Other("database call panicked".into())))).boxed()
+		}
+
+		// Run the iteration over the keys with the given prefix on the thread pool, sending its items
+		// to the returned stream. The iterators are not `Send`, so they cannot be held across an `await`:
+		// each chunk is read by an iterator of its own, and the task waits for the consumer in between
+		// without holding a pool thread.
+		fn spawn_iter(&self, col: u32, prefix: Vec<u8>) -> BoxStream<'static, (Box<[u8]>, Box<[u8]>)> {
+			let (mut sender, receiver) = mpsc::channel(ITER_CHUNK);
+			let db = self.db.clone();
+			self.pool.spawn_ok(async move {
+				let mut last = None;
+				loop {
+					let chunk = read_chunk(&*db, col, &prefix, last.as_deref());
+					let done = chunk.len() < ITER_CHUNK;
+					last = chunk.last().map(|(key, _)| key.clone());
+					// stop once the stream has been dropped
+					if sender.send_all(&mut stream::iter(chunk).map(Ok)).await.is_err() || done {
+						break;
+					}
+				}
+			});
+			receiver.boxed()
+		}
+	}
+
+	// Read the next chunk of the keys with the given prefix, after the given key if any.
+	fn read_chunk<DB: KeyValueDB>(db: &DB, col: u32, prefix: &[u8], after: Option<&[u8]>) -> Chunk {
+		let start = match after {
+			Some(key) => Bound::Excluded(key),
+			None => Bound::Included(prefix),
+		};
+		let end = end_prefix(prefix);
+		let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+		db.iter_range(col, start, end).take(ITER_CHUNK).collect()
+	}
+
+	impl<DB: MallocSizeOf> MallocSizeOf for ThreadPoolDB<DB> {
+		fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+			self.db.size_of(ops)
+		}
+	}
+
+	impl<DB: KeyValueDB + 'static> AsyncKeyValueDB for ThreadPoolDB<DB> {
+		fn get(&self, col: u32, key: &[u8]) -> BoxFuture<'_, Result<Option<DBValue>>> {
+			let key = key.to_vec();
+			self.spawn(move |db| db.get(col, &key))
+		}
+
+		fn write(&self, transaction: DBTransaction) -> BoxFuture<'_, Result<()>> {
+			self.spawn(move |db| db.write(transaction))
+		}
+
+		fn write_optimistic(&self, transaction: OptimisticTransaction) -> BoxFuture<'_, Result<()>> {
+			self.spawn(move |db| db.write_optimistic(transaction))
+		}
+
+		fn iter(&self, col: u32) -> BoxStream<'_, (Box<[u8]>, Box<[u8]>)> {
+			self.spawn_iter(col, Vec::new())
+		}
+
+		fn iter_with_prefix(&self, col: u32, prefix: &[u8]) -> BoxStream<'_, (Box<[u8]>, Box<[u8]>)> {
+			self.spawn_iter(col, prefix.to_vec())
+		}
+
+		fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+			self.db.snapshot()
+		}
+
+		fn restore(&self, new_db: &str) -> BoxFuture<'_, Result<()>> {
+			let new_db = new_db.to_owned();
+			self.spawn(move |db| db.restore(&new_db))
+		}
+
+		fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+			self.db.io_stats(kind)
+		}
+
+		fn compact_expired(&self) -> BoxFuture<'_, Result<()>> {
+			self.spawn(|db| db.compact_expired())
+		}
+	}
+}
@@ -0,0 +1,364 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Write-through LRU cache of the values of a database.
+
+use crate::{
+	DBOp, DBSnapshot, DBTransaction, DBValue, IoStats, IoStatsKind, KeyValueDB, OptimisticTransaction, Result,
+};
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+use std::{
+	collections::{BTreeMap, HashMap},
+	ops::Bound,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+};
+
+const POISONED_PROOF: &str = "the cache locks are never held while panicking; qed";
+
+// LRU cache of the values of a column, including the absent ones.
+#[derive(MallocSizeOf)]
+struct ColumnCache {
+	// maximum size of the cached keys and values in bytes
+	budget: usize,
+	size: usize,
+	// last use of each key, the least recently used one first
+	recency: BTreeMap<u64, Vec<u8>>,
+	entries: HashMap<Vec<u8>, (Option<DBValue>, u64)>,
+	next_tick: u64,
+	// incremented by each write, so that values read before it are not cached after it
+	generation: u64,
+}
+
+impl ColumnCache {
+	fn new(budget: usize) -> Self {
+		ColumnCache { budget, size: 0, recency: BTreeMap::new(), entries: HashMap::new(), next_tick: 0, generation: 0 }
+	}
+
+	fn tick(&mut self) -> u64 {
+		self.next_tick += 1;
+		self.next_tick
+	}
+
+	fn get(&mut self, key: &[u8]) -> Option<Option<DBValue>> {
+		let tick = self.tick();
+		let (value, last_used) = self.entries.get_mut(key)?;
+		let key = self.recency.remove(last_used).expect("every entry has its last use recorded; qed");
+		*last_used = tick;
+		self.recency.insert(tick, key);
+		Some(value.clone())
+	}
+
+	fn insert(&mut self, key: &[u8], value: Option<DBValue>) {
+		self.remove(key);
+		let size = key.len() + value.as_ref().map_or(0, |v| v.len());
+		if size > self.budget {
+			return;
+		}
+		while self.size + size > self.budget {
+			let key =
+				self.recency.values().next().cloned().expect("the cache is not empty as its size is positive; qed");
+			self.remove(&key);
+		}
+		let tick = self.tick();
+		self.size += size;
+		self.recency.insert(tick, key.to_vec());
+		self.entries.insert(key.to_vec(), (value, tick));
+	}
+
+	fn remove(&mut self, key: &[u8]) {
+		if let Some((value, last_used)) = self.entries.remove(key) {
+			self.recency.remove(&last_used);
+			self.size -= key.len() + value.map_or(0, |v| v.len());
+		}
+	}
+
+	fn remove_prefix(&mut self, prefix: &[u8]) {
+		let keys: Vec<Vec<u8>> = self.entries.keys().filter(|key| key.starts_with(prefix)).cloned().collect();
+		for key in keys {
+			self.remove(&key);
+		}
+	}
+
+	fn apply(&mut self, op: &DBOp) {
+		self.generation += 1;
+		match *op {
+			DBOp::Insert { ref key, ref value, .. } => self.insert(key, Some(value.clone())),
+			DBOp::Delete { ref key, .. } => self.insert(key, None),
+			// the merged value is only known to the database
+			DBOp::Merge { ref key, .. } => self.remove(key),
+			// the value must not be served after its expiry
+			DBOp::InsertWithExpiry { ref key, .. } => self.remove(key),
+			DBOp::DeletePrefix { ref prefix, .. } => self.remove_prefix(prefix),
+		}
+	}
+}
+
+#[derive(Default)]
+struct CacheStats {
+	hits: AtomicU64,
+	hit_bytes: AtomicU64,
+	// Overall counts at the previous `IoStatsKind::SincePrevious` query.
+	previous: Mutex<(u64, u64)>,
+}
+
+impl CacheStats {
+	fn tally_hit(&self, value: &Option<DBValue>) {
+		self.hits.fetch_add(1, Ordering::Relaxed);
+		self.hit_bytes.fetch_add(value.as_ref().map_or(0, |v| v.len()) as u64, Ordering::Relaxed);
+	}
+
+	fn take(&self, kind: &IoStatsKind) -> (u64, u64) {
+		let mut previous = self.previous.lock().expect(POISONED_PROOF);
+		let overall = (self.hits.load(Ordering::Relaxed), self.hit_bytes.load(Ordering::Relaxed));
+		match kind {
+			IoStatsKind::Overall => overall,
+			IoStatsKind::SincePrevious => {
+				let since_previous = (overall.0 - previous.0, overall.1 - previous.1);
+				*previous = overall;
+				since_previous
+			}
+		}
+	}
+}
+
+/// Database wrapper caching the most recently used values of the columns it has a budget for.
+///
+/// Written values are cached as they are written, deleted and missing ones are cached as absent,
+/// and merged ones are read back from the database. Writes made to the inner database directly
+/// are not noticed. Cache hits are reported as `IoStats::cache_reads` and `cache_read_bytes`.
+///
+/// Values inserted with an expiry are never cached, but the expiry of the values of columns
+/// with a time to live is not known to the cache, so such columns should not be given a budget.
+pub struct CachedDB<DB> {
+	db: DB,
+	columns: HashMap<u32, Mutex<ColumnCache>>,
+	// serializes the writes, so that the cache is updated in the order they are committed
+	write_lock: Mutex<()>,
+	stats: CacheStats,
+}
+
+impl<DB: KeyValueDB> CachedDB<DB> {
+	/// Wrap the database, without caching any column yet.
+	pub fn new(db: DB) -> Self {
+		CachedDB { db, columns: HashMap::new(), write_lock: Mutex::new(()), stats: CacheStats::default() }
+	}
+
+	/// Cache the values of the given column, up to `budget` bytes of keys and values.
+	pub fn with_column_budget(mut self, col: u32, budget: usize) -> Self {
+		self.columns.insert(col, Mutex::new(ColumnCache::new(budget)));
+		self
+	}
+
+	/// Get a reference to the inner database.
+	pub fn inner(&self) -> &DB {
+		&self.db
+	}
+
+	/// Unwrap the inner database.
+	pub fn into_inner(self) -> DB {
+		self.db
+	}
+
+	/// Total size in bytes of the keys and values cached for the column.
+	pub fn cached_size(&self, col: u32) -> usize {
+		self.columns.get(&col).map_or(0, |cache| cache.lock().expect(POISONED_PROOF).size)
+	}
+
+	fn update(&self, ops: &[DBOp]) {
+		for op in ops {
+			if let Some(cache) = self.columns.get(&op.col()) {
+				cache.lock().expect(POISONED_PROOF).apply(op);
+			}
+		}
+	}
+}
+
+impl<DB: MallocSizeOf> MallocSizeOf for CachedDB<DB> {
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		self.db.size_of(ops) + self.columns.size_of(ops)
+	}
+}
+
+impl<DB: KeyValueDB> KeyValueDB for CachedDB<DB> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		let cache = match self.columns.get(&col) {
+			Some(cache) => cache,
+			None => return self.db.get(col, key),
+		};
+		let generation = {
+			let mut cache = cache.lock().expect(POISONED_PROOF);
+			if let Some(value) = cache.get(key) {
+				self.stats.tally_hit(&value);
+				return Ok(value);
+			}
+			cache.generation
+		};
+		let value = self.db.get(col, key)?;
+		let mut cache = cache.lock().expect(POISONED_PROOF);
+		// the value might be stale if it was written in the meantime
+		if cache.generation == generation {
+			cache.insert(key, value.clone());
+		}
+		Ok(value)
+	}
+
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<Result<Option<DBValue>>> {
+		let cache = match self.columns.get(&col) {
+			Some(cache) => cache,
+			None => return self.db.get_many(col, keys),
+		};
+		let (mut values, generation) = {
+			let mut cache = cache.lock().expect(POISONED_PROOF);
+			let values: Vec<_> = keys.iter().map(|key| cache.get(key)).collect();
+			(values, cache.generation)
+		};
+		let missing: Vec<&[u8]> =
+			keys.iter().zip(&values).filter(|(_, value)| value.is_none()).map(|(key, _)| *key).collect();
+		let mut fetched = self.db.get_many(col, &missing).into_iter();
+		let mut cache = cache.lock().expect(POISONED_PROOF);
+		keys.iter()
+			.zip(values.iter_mut())
+			.map(|(key, value)| match value.take() {
+				Some(value) => {
+					self.stats.tally_hit(&value);
+					Ok(value)
+				}
+				None => {
+					let value = fetched.next().expect("one value is fetched for each missing key; qed")?;
+					if cache.generation == generation {
+						cache.insert(key, value.clone());
+					}
+					Ok(value)
+				}
+			})
+			.collect()
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		self.db.get_by_prefix(col, prefix)
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		let _write_lock = self.write_lock.lock().expect(POISONED_PROOF);
+		let ops = transaction.ops.clone();
+		self.db.write(transaction)?;
+		self.update(&ops);
+		Ok(())
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		let _write_lock = self.write_lock.lock().expect(POISONED_PROOF);
+		let ops = transaction.transaction.ops.clone();
+		self.db.write_optimistic(transaction)?;
+		self.update(&ops);
+		Ok(())
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter(col)
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter_with_prefix(col, prefix)
+	}
+
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter_range(col, start, end)
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter_range_rev(col, start, end)
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		self.db.snapshot()
+	}
+
+	fn restore(&self, new_db: &str) -> Result<()> {
+		let _write_lock = self.write_lock.lock().expect(POISONED_PROOF);
+		self.db.restore(new_db)?;
+		for cache in self.columns.values() {
+			let mut cache = cache.lock().expect(POISONED_PROOF);
+			*cache = ColumnCache { generation: cache.generation + 1, ..ColumnCache::new(cache.budget) };
+		}
+		Ok(())
+	}
+
+	fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+		let (hits, hit_bytes) = self.stats.take(&kind);
+		let mut stats = self.db.io_stats(kind);
+		stats.cache_reads += hits;
+		stats.cache_read_bytes += hit_bytes;
+		stats
+	}
+
+	fn compact_expired(&self) -> Result<()> {
+		self.db.compact_expired()
+	}
+
+	fn has_prefix(&self, col: u32, prefix: &[u8]) -> bool {
+		self.db.has_prefix(col, prefix)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ColumnCache;
+	use crate::{DBKey, DBOp};
+
+	#[test]
+	fn evicts_least_recently_used() {
+		let mut cache = ColumnCache::new(10);
+		cache.insert(b"a", Some(b"aaa".to_vec()));
+		cache.insert(b"b", Some(b"bbb".to_vec()));
+		assert!(cache.get(b"a").is_some());
+		cache.insert(b"c", Some(b"ccc".to_vec()));
+		assert_eq!(cache.size, 8);
+		assert_eq!(cache.get(b"a"), Some(Some(b"aaa".to_vec())));
+		assert_eq!(cache.get(b"b"), None);
+		assert_eq!(cache.get(b"c"), Some(Some(b"ccc".to_vec())));
+
+		// too large to be cached
+		cache.insert(b"d", Some(vec![0; 10]));
+		assert_eq!(cache.get(b"d"), None);
+		assert_eq!(cache.size, 8);
+	}
+
+	#[test]
+	fn applies_operations() {
+		let mut cache = ColumnCache::new(100);
+		cache.apply(&DBOp::Insert { col: 0, key: DBKey::from_slice(b"ab"), value: b"1".to_vec() });
+		cache.apply(&DBOp::Insert { col: 0, key: DBKey::from_slice(b"ac"), value: b"2".to_vec() });
+		cache.apply(&DBOp::Insert { col: 0, key: DBKey::from_slice(b"b"), value: b"3".to_vec() });
+		cache.apply(&DBOp::Delete { col: 0, key: DBKey::from_slice(b"b") });
+		assert_eq!(cache.get(b"b"), Some(None));
+
+		cache.apply(&DBOp::Merge { col: 0, key: DBKey::from_slice(b"ab"), operand: b"4".to_vec() });
+		assert_eq!(cache.get(b"ab"), None);
+		cache.apply(&DBOp::DeletePrefix { col: 0, prefix: DBKey::from_slice(b"a") });
+		assert_eq!(cache.get(b"ac"), None);
+		assert_eq!(cache.size, 1);
+	}
+}
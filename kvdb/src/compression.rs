@@ -0,0 +1,414 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Transparent compression of values with per-column codecs.
+
+use crate::{
+	DBOp, DBSnapshot, DBTransaction, DBValue, Error, IoStats, IoStatsKind, KeyValueDB, OptimisticTransaction, Result,
+	TransactionConflict,
+};
+use log::warn;
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+use std::{
+	collections::HashMap,
+	ops::Bound,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Mutex,
+	},
+};
+
+/// Compression codec of a column.
+///
+/// Every codec except `None` requires the feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+	/// Values are stored uncompressed.
+	None,
+	/// LZ4 block compression.
+	#[cfg(feature = "lz4")]
+	Lz4,
+	/// Zstandard compression with the given level.
+	#[cfg(feature = "zstd")]
+	Zstd(i32),
+	/// Snappy compression.
+	#[cfg(feature = "snappy")]
+	Snappy,
+}
+
+// Tags prefixed to the stored values to identify the codec they were compressed with.
+const TAG_NONE: u8 = 0;
+const TAG_LZ4: u8 = 1;
+const TAG_ZSTD: u8 = 2;
+const TAG_SNAPPY: u8 = 3;
+
+impl Codec {
+	fn compress(&self, value: &[u8]) -> Result<DBValue> {
+		let mut stored = Vec::with_capacity(value.len() + 1);
+		match *self {
+			Codec::None => {
+				stored.push(TAG_NONE);
+				stored.extend_from_slice(value);
+			}
+			#[cfg(feature = "lz4")]
+			Codec::Lz4 => {
+				stored.push(TAG_LZ4);
+				stored.extend_from_slice(&lz4_flex::compress_prepend_size(value));
+			}
+			#[cfg(feature = "zstd")]
+			Codec::Zstd(level) => {
+				stored.push(TAG_ZSTD);
+				stored.extend_from_slice(&zstd::stream::encode_all(value, level)?);
+			}
+			#[cfg(feature = "snappy")]
+			Codec::Snappy => {
+				stored.push(TAG_SNAPPY);
+				let compressed =
+					snap::raw::Encoder::new().compress_vec(value).map_err(|e| Error::Other(e.to_string()))?;
+				stored.extend_from_slice(&compressed);
+			}
+		}
+		Ok(stored)
+	}
+
+	// Decompress a stored value, whichever codec it was compressed with.
+	fn decompress(stored: &[u8]) -> Result<DBValue> {
+		let (tag, data) = match stored.split_first() {
+			Some((tag, data)) => (*tag, data),
+			None => return Err(Error::Corruption("compressed value is empty".into())),
+		};
+		match tag {
+			TAG_NONE => Ok(data.to_vec()),
+			#[cfg(feature = "lz4")]
+			TAG_LZ4 => lz4_flex::decompress_size_prepended(data).map_err(|e| Error::Corruption(e.to_string())),
+			#[cfg(feature = "zstd")]
+			TAG_ZSTD => zstd::stream::decode_all(data).map_err(|e| Error::Corruption(e.to_string())),
+			#[cfg(feature = "snappy")]
+			TAG_SNAPPY => snap::raw::Decoder::new().decompress_vec(data).map_err(|e| Error::Corruption(e.to_string())),
+			_ if [TAG_LZ4, TAG_ZSTD, TAG_SNAPPY].contains(&tag) => {
+				Err(Error::NotSupported(format!("value compressed with a disabled codec (tag {})", tag)))
+			}
+			_ => Err(Error::Corruption(format!("unknown compression codec tag {}", tag))),
+		}
+	}
+}
+
+#[derive(Default)]
+struct CompressionStats {
+	compressed_bytes: AtomicU64,
+	uncompressed_bytes: AtomicU64,
+	// Overall counts at the previous `IoStatsKind::SincePrevious` query.
+	previous: Mutex<(u64, u64)>,
+}
+
+impl CompressionStats {
+	fn tally(&self, compressed: usize, uncompressed: usize) {
+		self.compressed_bytes.fetch_add(compressed as u64, Ordering::Relaxed);
+		self.uncompressed_bytes.fetch_add(uncompressed as u64, Ordering::Relaxed);
+	}
+
+	fn take(&self, kind: &IoStatsKind) -> (u64, u64) {
+		let mut previous = self.previous.lock().expect("the lock is never held while panicking; qed");
+		let overall = (self.compressed_bytes.load(Ordering::Relaxed), self.uncompressed_bytes.load(Ordering::Relaxed));
+		match kind {
+			IoStatsKind::Overall => overall,
+			IoStatsKind::SincePrevious => {
+				let since_previous = (overall.0 - previous.0, overall.1 - previous.1);
+				*previous = overall;
+				since_previous
+			}
+		}
+	}
+}
+
+/// Database wrapper compressing the values of the columns it has a codec for.
+///
+/// The values of these columns are stored prefixed with a tag identifying their codec, so the codec
+/// of a column may be changed at any time, but these columns must only be accessed through the wrapper.
+/// Merging into them is not supported. Values of the other columns are stored as they are.
+///
+/// The values failing to decompress are logged and skipped by the iterators and `get_by_prefix`,
+/// while `get` and `get_many` return the error.
+pub struct CompressedDB<DB> {
+	db: DB,
+	codecs: HashMap<u32, Codec>,
+	stats: CompressionStats,
+}
+
+impl<DB: KeyValueDB> CompressedDB<DB> {
+	/// Wrap the database, without compressing any column yet.
+	pub fn new(db: DB) -> Self {
+		CompressedDB { db, codecs: HashMap::new(), stats: CompressionStats::default() }
+	}
+
+	/// Compress the values of the given column with the codec.
+	pub fn with_codec(mut self, col: u32, codec: Codec) -> Self {
+		self.codecs.insert(col, codec);
+		self
+	}
+
+	/// Get a reference to the inner database.
+	pub fn inner(&self) -> &DB {
+		&self.db
+	}
+
+	/// Unwrap the inner database.
+	pub fn into_inner(self) -> DB {
+		self.db
+	}
+
+	fn compress_transaction(&self, transaction: DBTransaction) -> Result<DBTransaction> {
+		let mut ops = Vec::with_capacity(transaction.ops.len());
+		for op in transaction.ops {
+			let op = match op {
+				DBOp::Insert { col, key, value } => match self.codecs.get(&col) {
+					Some(codec) => {
+						let stored = codec.compress(&value)?;
+						self.stats.tally(stored.len(), value.len());
+						DBOp::Insert { col, key, value: stored }
+					}
+					None => DBOp::Insert { col, key, value },
+				},
+				DBOp::InsertWithExpiry { col, key, value, expiry } => match self.codecs.get(&col) {
+					Some(codec) => {
+						let stored = codec.compress(&value)?;
+						self.stats.tally(stored.len(), value.len());
+						DBOp::InsertWithExpiry { col, key, value: stored, expiry }
+					}
+					None => DBOp::InsertWithExpiry { col, key, value, expiry },
+				},
+				DBOp::Merge { col, .. } if self.codecs.contains_key(&col) => {
+					return Err(Error::NotSupported(format!("merge into compressed column {}", col)))
+				}
+				op => op,
+			};
+			ops.push(op);
+		}
+		Ok(DBTransaction { ops })
+	}
+}
+
+// Decompress the stored values of a column when it has a codec.
+#[derive(Clone, Copy)]
+struct Decompressor<'a> {
+	col: u32,
+	compressed: bool,
+	stats: &'a CompressionStats,
+}
+
+impl<'a> Decompressor<'a> {
+	fn new(codecs: &HashMap<u32, Codec>, col: u32, stats: &'a CompressionStats) -> Self {
+		Decompressor { col, compressed: codecs.contains_key(&col), stats }
+	}
+
+	fn value(&self, stored: Option<DBValue>) -> Result<Option<DBValue>> {
+		match stored {
+			Some(ref stored) if self.compressed => {
+				let value = Codec::decompress(stored)?;
+				self.stats.tally(stored.len(), value.len());
+				Ok(Some(value))
+			}
+			stored => Ok(stored),
+		}
+	}
+
+	fn boxed(&self, stored: Box<[u8]>) -> Result<Box<[u8]>> {
+		if self.compressed {
+			let value = Codec::decompress(&stored)?;
+			self.stats.tally(stored.len(), value.len());
+			Ok(value.into_boxed_slice())
+		} else {
+			Ok(stored)
+		}
+	}
+
+	// The decompressed value, `None` if it failed to decompress.
+	fn boxed_or_skip(&self, key: &[u8], stored: Box<[u8]>) -> Option<Box<[u8]>> {
+		match self.boxed(stored) {
+			Ok(value) => Some(value),
+			Err(err) => {
+				warn!("Skipping a value failing to decompress in col {} at {:?}: {}", self.col, key, err);
+				None
+			}
+		}
+	}
+
+	fn iter<'b>(
+		self,
+		iter: Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b>
+	where
+		'a: 'b,
+	{
+		if self.compressed {
+			Box::new(iter.filter_map(move |(key, stored)| self.boxed_or_skip(&key, stored).map(|value| (key, value))))
+		} else {
+			iter
+		}
+	}
+}
+
+impl<DB: MallocSizeOf> MallocSizeOf for CompressedDB<DB> {
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		self.db.size_of(ops)
+	}
+}
+
+impl<DB: KeyValueDB> KeyValueDB for CompressedDB<DB> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		Decompressor::new(&self.codecs, col, &self.stats).value(self.db.get(col, key)?)
+	}
+
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<Result<Option<DBValue>>> {
+		let decompressor = Decompressor::new(&self.codecs, col, &self.stats);
+		self.db.get_many(col, keys).into_iter().map(|stored| decompressor.value(stored?)).collect()
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		let stored = self.db.get_by_prefix(col, prefix)?;
+		// falls back to the first value decompressing successfully
+		Decompressor::new(&self.codecs, col, &self.stats)
+			.boxed_or_skip(prefix, stored)
+			.or_else(|| self.iter_with_prefix(col, prefix).next().map(|(_, value)| value))
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		self.db.write(self.compress_transaction(transaction)?)
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		// the values read are recorded uncompressed: each stored value is read once, checked once
+		// decompressed, and recorded as stored for the inner database to check it again
+		let mut compressed = OptimisticTransaction::new();
+		for read in &transaction.reads {
+			let stored = self.db.get(read.col, &read.key)?;
+			if Decompressor::new(&self.codecs, read.col, &self.stats).value(stored.clone())? != read.value {
+				return Err(TransactionConflict { col: read.col, key: read.key.clone() }.into());
+			}
+			compressed.record_read(read.col, &read.key, stored);
+		}
+		compressed.transaction = self.compress_transaction(transaction.transaction)?;
+		self.db.write_optimistic(compressed)
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Decompressor::new(&self.codecs, col, &self.stats).iter(self.db.iter(col))
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Decompressor::new(&self.codecs, col, &self.stats).iter(self.db.iter_with_prefix(col, prefix))
+	}
+
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Decompressor::new(&self.codecs, col, &self.stats).iter(self.db.iter_range(col, start, end))
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Decompressor::new(&self.codecs, col, &self.stats).iter(self.db.iter_range_rev(col, start, end))
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		Box::new(CompressedSnapshot { snapshot: self.db.snapshot(), codecs: &self.codecs, stats: &self.stats })
+	}
+
+	fn restore(&self, new_db: &str) -> Result<()> {
+		self.db.restore(new_db)
+	}
+
+	fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+		let (compressed_bytes, uncompressed_bytes) = self.stats.take(&kind);
+		let mut stats = self.db.io_stats(kind);
+		stats.compressed_bytes += compressed_bytes;
+		stats.uncompressed_bytes += uncompressed_bytes;
+		stats
+	}
+
+	fn compact_expired(&self) -> Result<()> {
+		self.db.compact_expired()
+	}
+
+	fn has_key(&self, col: u32, key: &[u8]) -> Result<bool> {
+		self.db.has_key(col, key)
+	}
+
+	fn has_prefix(&self, col: u32, prefix: &[u8]) -> bool {
+		self.db.has_prefix(col, prefix)
+	}
+}
+
+struct CompressedSnapshot<'a> {
+	snapshot: Box<dyn DBSnapshot + 'a>,
+	codecs: &'a HashMap<u32, Codec>,
+	stats: &'a CompressionStats,
+}
+
+impl<'a> DBSnapshot for CompressedSnapshot<'a> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		Decompressor::new(self.codecs, col, self.stats).value(self.snapshot.get(col, key)?)
+	}
+
+	fn iter<'b>(&'b self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b> {
+		Decompressor::new(self.codecs, col, self.stats).iter(self.snapshot.iter(col))
+	}
+
+	fn iter_with_prefix<'b>(
+		&'b self,
+		col: u32,
+		prefix: &'b [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b> {
+		Decompressor::new(self.codecs, col, self.stats).iter(self.snapshot.iter_with_prefix(col, prefix))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Codec;
+
+	fn codecs() -> Vec<Codec> {
+		vec![
+			Codec::None,
+			#[cfg(feature = "lz4")]
+			Codec::Lz4,
+			#[cfg(feature = "zstd")]
+			Codec::Zstd(3),
+			#[cfg(feature = "snappy")]
+			Codec::Snappy,
+		]
+	}
+
+	#[test]
+	fn codecs_roundtrip() {
+		let value = b"a value compressing well, well, well, well, well, well, well".to_vec();
+		for codec in codecs() {
+			let stored = codec.compress(&value).unwrap();
+			assert_eq!(Codec::decompress(&stored).unwrap(), value);
+			let empty = codec.compress(&[]).unwrap();
+			assert!(Codec::decompress(&empty).unwrap().is_empty());
+		}
+	}
+
+	#[test]
+	fn decompress_fails_on_unknown_tag() {
+		assert!(Codec::decompress(&[]).is_err());
+		assert!(Codec::decompress(&[42, 1, 2, 3]).is_err());
+	}
+}
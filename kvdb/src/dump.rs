@@ -0,0 +1,253 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Backend-neutral dump format to export and import the content of a database.
+//!
+//! A dump starts with the `DUMP_MAGIC` bytes and a version byte, followed by records made of
+//! a kind byte, the length of the record payload as a little-endian `u32` and the payload:
+//!
+//! - `COLUMN`: the column of the following entries, as a little-endian `u32`;
+//! - `ENTRY`: the length of the key as a little-endian `u32`, the key and the value;
+//! - `END`: the number of entries as a little-endian `u64` and the CRC-32 of all the
+//!   preceding records as a little-endian `u32`.
+
+use crate::{DBTransaction, Error, KeyValueDB, Result};
+use std::{
+	io::{BufReader, BufWriter, Read, Write},
+	mem,
+	ops::Range,
+};
+
+/// First bytes of a dump.
+pub const DUMP_MAGIC: &[u8; 8] = b"KVDBDUMP";
+/// Version of the dump format written by `export`.
+pub const DUMP_VERSION: u8 = 1;
+
+const COLUMN: u8 = 1;
+const ENTRY: u8 = 2;
+const END: u8 = 3;
+
+// CRC-32 (IEEE 802.3) checksum.
+struct Crc32 {
+	table: [u32; 256],
+	crc: u32,
+}
+
+impl Crc32 {
+	fn new() -> Self {
+		let mut table = [0u32; 256];
+		for (i, entry) in table.iter_mut().enumerate() {
+			let mut crc = i as u32;
+			for _ in 0..8 {
+				crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+			}
+			*entry = crc;
+		}
+		Crc32 { table, crc: !0 }
+	}
+
+	fn update(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.crc = self.table[((self.crc ^ *byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+		}
+	}
+
+	fn value(&self) -> u32 {
+		!self.crc
+	}
+}
+
+struct RecordWriter<W> {
+	writer: W,
+	crc: Crc32,
+}
+
+impl<W: Write> RecordWriter<W> {
+	fn write(&mut self, kind: u8, payload: &[&[u8]]) -> Result<()> {
+		let len = payload.iter().map(|part| part.len()).sum::<usize>();
+		if len > u32::MAX as usize {
+			return Err(Error::NotSupported(format!("dump record of {} bytes", len)));
+		}
+		self.write_checksummed(&[kind])?;
+		self.write_checksummed(&(len as u32).to_le_bytes())?;
+		for part in payload {
+			self.write_checksummed(part)?;
+		}
+		Ok(())
+	}
+
+	fn write_checksummed(&mut self, bytes: &[u8]) -> Result<()> {
+		self.crc.update(bytes);
+		self.writer.write_all(bytes)?;
+		Ok(())
+	}
+}
+
+/// Export the content of the given columns of the database to the writer in the dump format.
+///
+/// The content is read from a snapshot, so writes made during the export are not exported.
+pub fn export<DB, W>(db: &DB, columns: Range<u32>, writer: W) -> Result<()>
+where
+	DB: KeyValueDB + ?Sized,
+	W: Write,
+{
+	let mut writer = BufWriter::new(writer);
+	writer.write_all(DUMP_MAGIC)?;
+	writer.write_all(&[DUMP_VERSION])?;
+	let mut records = RecordWriter { writer, crc: Crc32::new() };
+	let snapshot = db.snapshot();
+	let mut entries = 0u64;
+	for col in columns {
+		// fail early on missing columns, as iterators are empty for these
+		snapshot.get(col, &[])?;
+		records.write(COLUMN, &[&col.to_le_bytes()])?;
+		for (key, value) in snapshot.iter(col) {
+			if key.len() > u32::MAX as usize {
+				return Err(Error::NotSupported(format!("dump key of {} bytes", key.len())));
+			}
+			records.write(ENTRY, &[&(key.len() as u32).to_le_bytes(), &key, &value])?;
+			entries += 1;
+		}
+	}
+	let crc = records.crc.value();
+	records.write(END, &[&entries.to_le_bytes(), &crc.to_le_bytes()])?;
+	records.writer.flush()?;
+	Ok(())
+}
+
+struct RecordReader<R> {
+	reader: R,
+	crc: Crc32,
+}
+
+impl<R: Read> RecordReader<R> {
+	fn read(&mut self) -> Result<(u8, Vec<u8>)> {
+		let mut header = [0u8; 5];
+		self.read_exact(&mut header)?;
+		self.crc.update(&header);
+		let len = read_u32(&header[1..]) as usize;
+		let mut payload = Vec::new();
+		// read gradually, in case the length is corrupted
+		(&mut self.reader).take(len as u64).read_to_end(&mut payload)?;
+		if payload.len() != len {
+			return Err(unexpected_end());
+		}
+		self.crc.update(&payload);
+		Ok((header[0], payload))
+	}
+
+	fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+		self.reader.read_exact(buf).map_err(|e| match e.kind() {
+			std::io::ErrorKind::UnexpectedEof => unexpected_end(),
+			_ => e.into(),
+		})
+	}
+}
+
+fn unexpected_end() -> Error {
+	Error::Corruption("unexpected end of dump".into())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+	let mut buf = [0u8; 4];
+	buf.copy_from_slice(&bytes[..4]);
+	u32::from_le_bytes(buf)
+}
+
+fn invalid_record(kind: u8) -> Error {
+	Error::Corruption(format!("invalid dump record of kind {}", kind))
+}
+
+/// Import a dump written by `export` into the database, returning the number of entries imported.
+///
+/// The entries are written as they are read, in transactions of about 4 MiB, so the memory used
+/// does not depend on the size of the dump. The checksum at the end of the dump is verified once
+/// all the entries have been read: if the dump is invalid, the entries already written remain in
+/// the database and the returned `Error::Corruption` reports them.
+pub fn import<DB, R>(db: &DB, reader: R) -> Result<u64>
+where
+	DB: KeyValueDB + ?Sized,
+	R: Read,
+{
+	let mut records = RecordReader { reader: BufReader::new(reader), crc: Crc32::new() };
+	let mut header = [0u8; 9];
+	records.read_exact(&mut header)?;
+	if header[..8] != DUMP_MAGIC[..] {
+		return Err(Error::Corruption("not a dump".into()));
+	}
+	if header[8] != DUMP_VERSION {
+		return Err(Error::NotSupported(format!("dump version {}", header[8])));
+	}
+
+	let mut written = Written { entries: 0, last: None };
+	import_records(db, &mut records, &mut written).map_err(|err| match (err, written.last) {
+		(Error::Corruption(msg), Some((col, key))) => Error::Corruption(format!(
+			"{}; the first {} entries of the dump, up to the key {:?} of column {}, were already imported",
+			msg, written.entries, key, col
+		)),
+		(err, _) => err,
+	})
+}
+
+// Size of the keys and values of the entries written by `import` at once.
+const IMPORT_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+// Entries of the dump written so far by `import`.
+struct Written {
+	entries: u64,
+	// column and key of the last entry written
+	last: Option<(u32, Vec<u8>)>,
+}
+
+fn import_records<DB, R>(db: &DB, records: &mut RecordReader<R>, written: &mut Written) -> Result<u64>
+where
+	DB: KeyValueDB + ?Sized,
+	R: Read,
+{
+	let mut col = None;
+	let mut transaction = DBTransaction::new();
+	let mut batch_bytes = 0;
+	let mut entries = 0u64;
+	loop {
+		let crc = records.crc.value();
+		let (kind, payload) = records.read()?;
+		match kind {
+			COLUMN if payload.len() == 4 => col = Some(read_u32(&payload)),
+			ENTRY if payload.len() >= 4 => {
+				let col = col.ok_or_else(|| invalid_record(kind))?;
+				let key_len = read_u32(&payload) as usize;
+				if payload.len() - 4 < key_len {
+					return Err(invalid_record(kind));
+				}
+				let (key, value) = payload[4..].split_at(key_len);
+				transaction.put(col, key, value);
+				entries += 1;
+				batch_bytes += payload.len() - 4;
+				if batch_bytes >= IMPORT_BATCH_BYTES {
+					db.write(mem::replace(&mut transaction, DBTransaction::new()))?;
+					batch_bytes = 0;
+					written.entries = entries;
+					written.last = Some((col, key.to_vec()));
+				}
+			}
+			END if payload.len() == 12 => {
+				let mut count = [0u8; 8];
+				count.copy_from_slice(&payload[..8]);
+				if read_u32(&payload[8..]) != crc {
+					return Err(Error::Corruption("dump checksum mismatch".into()));
+				}
+				if u64::from_le_bytes(count) != entries {
+					return Err(Error::Corruption("dump entry count mismatch".into()));
+				}
+				db.write(transaction)?;
+				return Ok(entries);
+			}
+			_ => return Err(invalid_record(kind)),
+		}
+	}
+}
@@ -0,0 +1,86 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Errors returned by key-value databases.
+
+use crate::TransactionConflict;
+use std::{error, fmt, io};
+
+/// Result of a database operation.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error returned by a key-value database.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+	/// The column index is out of bounds.
+	UnknownColumn(u32),
+	/// The database has been closed.
+	Closed,
+	/// The database is corrupted.
+	Corruption(String),
+	/// There is not enough disk space left.
+	NoSpace(String),
+	/// A value read by an `OptimisticTransaction` has changed since.
+	TransactionConflict(TransactionConflict),
+	/// The operation is not supported by the database.
+	NotSupported(String),
+	/// A schema migration cannot be applied to the database.
+	InvalidMigration(String),
+	/// An I/O error.
+	Io(io::Error),
+	/// Any other error reported by the database.
+	Other(String),
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match *self {
+			Error::TransactionConflict(ref conflict) => Some(conflict),
+			Error::Io(ref err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			Error::UnknownColumn(col) => write!(f, "No such column family: {}", col),
+			Error::Closed => write!(f, "Database is closed"),
+			Error::Corruption(ref msg) => write!(f, "Database is corrupted: {}", msg),
+			Error::NoSpace(ref msg) => write!(f, "No space left: {}", msg),
+			Error::TransactionConflict(ref conflict) => conflict.fmt(f),
+			Error::NotSupported(ref msg) => write!(f, "Not supported: {}", msg),
+			Error::InvalidMigration(ref msg) => write!(f, "Invalid migration: {}", msg),
+			Error::Io(ref err) => err.fmt(f),
+			Error::Other(ref msg) => f.write_str(msg),
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Error {
+		Error::Io(err)
+	}
+}
+
+impl From<TransactionConflict> for Error {
+	fn from(conflict: TransactionConflict) -> Error {
+		Error::TransactionConflict(conflict)
+	}
+}
+
+impl From<Error> for io::Error {
+	fn from(err: Error) -> io::Error {
+		match err {
+			Error::Io(err) => err,
+			err => io::Error::other(err),
+		}
+	}
+}
@@ -16,6 +16,18 @@ pub enum Kind {
 	SincePrevious,
 }
 
+/// Write stall condition of a database, when its background work falls behind the writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteStall {
+	/// Writes are not stalled.
+	#[default]
+	None,
+	/// Writes are slowed down to the given rate, in bytes per second.
+	Delayed(u64),
+	/// Writes are stopped until the background work catches up.
+	Stopped,
+}
+
 /// Statistic for the `span` period
 #[derive(Debug, Clone)]
 pub struct IoStats {
@@ -33,6 +45,14 @@ pub struct IoStats {
 	pub cache_read_bytes: u64,
 	/// Number of bytes write
 	pub bytes_written: u64,
+	/// Number of compressed bytes of the values compressed or decompressed.
+	pub compressed_bytes: u64,
+	/// Number of uncompressed bytes of the values compressed or decompressed.
+	pub uncompressed_bytes: u64,
+	/// Time the writes spent stalled.
+	pub write_stall_duration: std::time::Duration,
+	/// Write stall condition at the end of the statistic period.
+	pub write_stall: WriteStall,
 	/// Start of the statistic period.
 	pub started: std::time::Instant,
 	/// Total duration of the statistic period.
@@ -50,6 +70,10 @@ impl IoStats {
 			bytes_read: 0,
 			cache_read_bytes: 0,
 			bytes_written: 0,
+			compressed_bytes: 0,
+			uncompressed_bytes: 0,
+			write_stall_duration: std::time::Duration::default(),
+			write_stall: WriteStall::None,
 			started: std::time::Instant::now(),
 			span: std::time::Duration::default(),
 		}
@@ -63,6 +87,14 @@ impl IoStats {
 		self.transactions as f64 / self.writes as f64
 	}
 
+	/// Ratio of the compressed size to the uncompressed size of the values compressed or decompressed.
+	pub fn compression_ratio(&self) -> f64 {
+		if self.uncompressed_bytes == 0 {
+			return 0.0;
+		}
+		self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+	}
+
 	/// Read operations per second.
 	pub fn reads_per_sec(&self) -> f64 {
 		if self.span.as_secs_f64() == 0.0 {
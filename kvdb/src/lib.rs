@@ -9,9 +9,20 @@
 //! Key-Value store abstraction.
 
 use smallvec::SmallVec;
-use std::io;
+use std::{ops::Bound, time::SystemTime};
 
+#[cfg(feature = "async")]
+mod async_db;
+mod cache;
+mod compression;
+mod dump;
+mod error;
 mod io_stats;
+mod optimistic;
+mod overlay;
+mod prefixed;
+mod schema;
+mod subscription;
 
 /// Required length of prefixes.
 pub const PREFIX_LEN: usize = 12;
@@ -21,7 +32,20 @@ pub type DBValue = Vec<u8>;
 /// Database keys.
 pub type DBKey = SmallVec<[u8; 32]>;
 
-pub use io_stats::{IoStats, Kind as IoStatsKind};
+#[cfg(feature = "thread-pool")]
+pub use async_db::ThreadPoolDB;
+#[cfg(feature = "async")]
+pub use async_db::{AsyncKeyValueDB, BlockingDB};
+pub use cache::CachedDB;
+pub use compression::{Codec, CompressedDB};
+pub use dump::{export, import, DUMP_MAGIC, DUMP_VERSION};
+pub use error::{Error, Result};
+pub use io_stats::{IoStats, Kind as IoStatsKind, WriteStall};
+pub use optimistic::{DBRead, OptimisticTransaction, TransactionConflict};
+pub use overlay::OverlayDB;
+pub use prefixed::PrefixedDB;
+pub use schema::{Migration, MigrationStep, Schema};
+pub use subscription::{SubscribableDB, SubscriptionFilter, SubscriptionId};
 
 /// Write transaction. Batches a sequence of put/delete operations for efficiency.
 #[derive(Default, Clone, PartialEq)]
@@ -36,6 +60,8 @@ pub enum DBOp {
 	Insert { col: u32, key: DBKey, value: DBValue },
 	Delete { col: u32, key: DBKey },
 	DeletePrefix { col: u32, prefix: DBKey },
+	Merge { col: u32, key: DBKey, operand: DBValue },
+	InsertWithExpiry { col: u32, key: DBKey, value: DBValue, expiry: SystemTime },
 }
 
 impl DBOp {
@@ -45,6 +71,8 @@ impl DBOp {
 			DBOp::Insert { ref key, .. } => key,
 			DBOp::Delete { ref key, .. } => key,
 			DBOp::DeletePrefix { ref prefix, .. } => prefix,
+			DBOp::Merge { ref key, .. } => key,
+			DBOp::InsertWithExpiry { ref key, .. } => key,
 		}
 	}
 
@@ -54,6 +82,8 @@ impl DBOp {
 			DBOp::Insert { col, .. } => col,
 			DBOp::Delete { col, .. } => col,
 			DBOp::DeletePrefix { col, .. } => col,
+			DBOp::Merge { col, .. } => col,
+			DBOp::InsertWithExpiry { col, .. } => col,
 		}
 	}
 }
@@ -90,6 +120,37 @@ impl DBTransaction {
 	pub fn delete_prefix(&mut self, col: u32, prefix: &[u8]) {
 		self.ops.push(DBOp::DeletePrefix { col, prefix: DBKey::from_slice(prefix) });
 	}
+
+	/// Merge the operand into the value of the key using the merge operator of the column.
+	/// Fails upon write if the column has no merge operator.
+	pub fn merge(&mut self, col: u32, key: &[u8], operand: &[u8]) {
+		self.ops.push(DBOp::Merge { col, key: DBKey::from_slice(key), operand: operand.to_vec() });
+	}
+
+	/// Insert a key-value pair expiring at the given time instead of after the time to live of the column.
+	/// Fails upon write if the column has no time to live.
+	pub fn put_with_expiry(&mut self, col: u32, key: &[u8], value: &[u8], expiry: SystemTime) {
+		self.ops.push(DBOp::InsertWithExpiry { col, key: DBKey::from_slice(key), value: value.to_vec(), expiry });
+	}
+}
+
+/// Function merging operands into the existing value of a key, see `MergeOperator::merge`.
+pub type MergeFn = fn(key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> DBValue;
+
+/// Merge operator, combining the operands of `DBOp::Merge` with the stored value of a key.
+///
+/// Useful for values which are updated incrementally, like counters or append-only lists,
+/// to avoid reading the value before writing it back.
+pub trait MergeOperator {
+	/// Name of the operator.
+	const NAME: &'static str;
+
+	/// Merge the operands, oldest first, into the existing value of the key,
+	/// `None` if the key is absent.
+	///
+	/// Backends may merge the operands one at a time or several at once,
+	/// so both must give the same result.
+	fn merge(key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> DBValue;
 }
 
 /// Generic key-value database.
@@ -108,13 +169,24 @@ pub trait KeyValueDB: Sync + Send + parity_util_mem::MallocSizeOf {
 	}
 
 	/// Get a value by key.
-	fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>>;
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>>;
+
+	/// Get the values of several keys at once, in the order of `keys`.
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<Result<Option<DBValue>>> {
+		keys.iter().map(|key| self.get(col, key)).collect()
+	}
 
 	/// Get the first value matching the given prefix.
 	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>>;
 
 	/// Write a transaction of changes to the backing store.
-	fn write(&self, transaction: DBTransaction) -> io::Result<()>;
+	fn write(&self, transaction: DBTransaction) -> Result<()>;
+
+	/// Write the changes of an optimistic transaction to the backing store, provided
+	/// none of the values it read has changed since.
+	///
+	/// On conflict nothing is written and `Error::TransactionConflict` is returned.
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()>;
 
 	/// Iterate over the data for a given column.
 	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
@@ -127,8 +199,48 @@ pub trait KeyValueDB: Sync + Send + parity_util_mem::MallocSizeOf {
 		prefix: &'a [u8],
 	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
 
+	/// Iterate over the data for a given column, returning all key/value pairs
+	/// with keys within the `start` and `end` bounds, in ascending key order.
+	///
+	/// The default implementation filters `iter`; implementations are expected
+	/// to override it with a native range seek.
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		Box::new(
+			self.iter(col)
+				.skip_while(move |(key, _)| !is_after_start(key, start))
+				.take_while(move |(key, _)| is_before_end(key, end)),
+		)
+	}
+
+	/// Iterate over the data for a given column, returning all key/value pairs
+	/// with keys within the `start` and `end` bounds, in descending key order.
+	///
+	/// Using `Bound::Unbounded` for both ends iterates over the whole column in reverse.
+	/// The default implementation buffers the result of `iter_range`; implementations
+	/// are expected to override it with a native reverse seek.
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let pairs: Vec<_> = self.iter_range(col, start, end).collect();
+		Box::new(pairs.into_iter().rev())
+	}
+
+	/// Take a read-only snapshot of the database.
+	///
+	/// All reads made through the snapshot observe the state of every column at the
+	/// time the snapshot was taken, regardless of any writes committed afterwards.
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a>;
+
 	/// Attempt to replace this database with a new one located at the given path.
-	fn restore(&self, new_db: &str) -> io::Result<()>;
+	fn restore(&self, new_db: &str) -> Result<()>;
 
 	/// Query statistics.
 	///
@@ -140,8 +252,16 @@ pub trait KeyValueDB: Sync + Send + parity_util_mem::MallocSizeOf {
 		IoStats::empty()
 	}
 
+	/// Remove the expired values of the columns with a time to live.
+	///
+	/// Expired values are hidden from reads as soon as they expire, but may only be removed
+	/// from storage later on, depending on the implementation. By default nothing expires.
+	fn compact_expired(&self) -> Result<()> {
+		Ok(())
+	}
+
 	/// Check for the existence of a value by key.
-	fn has_key(&self, col: u32, key: &[u8]) -> io::Result<bool> {
+	fn has_key(&self, col: u32, key: &[u8]) -> Result<bool> {
 		self.get(col, key).map(|opt| opt.is_some())
 	}
 
@@ -151,6 +271,25 @@ pub trait KeyValueDB: Sync + Send + parity_util_mem::MallocSizeOf {
 	}
 }
 
+/// Read-only view of a `KeyValueDB` pinned to a point in time.
+///
+/// See `KeyValueDB::snapshot`.
+pub trait DBSnapshot {
+	/// Get a value by key.
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>>;
+
+	/// Iterate over the data for a given column.
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+
+	/// Iterate over the data for a given column, returning all key/value pairs
+	/// where the key starts with the given prefix.
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+}
+
 /// For a given start prefix (inclusive), returns the correct end prefix (non-inclusive).
 /// This assumes the key bytes are ordered in lexicographical order.
 /// Since key length is not limited, for some case we return `None` because there is
@@ -168,6 +307,24 @@ pub fn end_prefix(prefix: &[u8]) -> Option<Vec<u8>> {
 	}
 }
 
+// Returns true if the `key` does not precede the `start` bound.
+fn is_after_start(key: &[u8], start: Bound<&[u8]>) -> bool {
+	match start {
+		Bound::Included(start) => key >= start,
+		Bound::Excluded(start) => key > start,
+		Bound::Unbounded => true,
+	}
+}
+
+// Returns true if the `key` does not exceed the `end` bound.
+fn is_before_end(key: &[u8], end: Bound<&[u8]>) -> bool {
+	match end {
+		Bound::Included(end) => key <= end,
+		Bound::Excluded(end) => key < end,
+		Bound::Unbounded => true,
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::end_prefix;
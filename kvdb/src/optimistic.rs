@@ -0,0 +1,112 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Read-modify-write transactions with optimistic conflict detection.
+
+use crate::{DBKey, DBTransaction, DBValue, Error, KeyValueDB, Result};
+use std::{error, fmt};
+
+/// A value read by an `OptimisticTransaction`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DBRead {
+	/// Column the value was read from.
+	pub col: u32,
+	/// Key the value was read at.
+	pub key: DBKey,
+	/// The value observed, `None` if the key was absent.
+	pub value: Option<DBValue>,
+}
+
+/// Read-modify-write transaction.
+///
+/// Records every value read through it. When written with `KeyValueDB::write_optimistic`,
+/// the changes are only applied if all the recorded values are still current, in the manner
+/// of a compare-and-swap. Otherwise nothing is written and the write fails with
+/// `Error::TransactionConflict`.
+#[derive(Default, Clone, PartialEq)]
+pub struct OptimisticTransaction {
+	/// Values read by the transaction.
+	pub reads: Vec<DBRead>,
+	/// Changes to apply if none of the values read has changed.
+	pub transaction: DBTransaction,
+}
+
+impl OptimisticTransaction {
+	/// Create new transaction.
+	pub fn new() -> OptimisticTransaction {
+		OptimisticTransaction { reads: Vec::new(), transaction: DBTransaction::new() }
+	}
+
+	/// Get a value by key from the database and record it as read.
+	///
+	/// Note that changes made by the transaction itself are not visible here.
+	pub fn get(&mut self, db: &dyn KeyValueDB, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		let value = db.get(col, key)?;
+		self.record_read(col, key, value.clone());
+		Ok(value)
+	}
+
+	/// Record a value that was read by other means as part of the transaction.
+	pub fn record_read(&mut self, col: u32, key: &[u8], value: Option<DBValue>) {
+		self.reads.push(DBRead { col, key: DBKey::from_slice(key), value });
+	}
+
+	/// Insert a key-value pair in the transaction. Any existing value will be overwritten upon write.
+	pub fn put(&mut self, col: u32, key: &[u8], value: &[u8]) {
+		self.transaction.put(col, key, value)
+	}
+
+	/// Insert a key-value pair in the transaction. Any existing value will be overwritten upon write.
+	pub fn put_vec(&mut self, col: u32, key: &[u8], value: Vec<u8>) {
+		self.transaction.put_vec(col, key, value)
+	}
+
+	/// Delete value by key.
+	pub fn delete(&mut self, col: u32, key: &[u8]) {
+		self.transaction.delete(col, key)
+	}
+
+	/// Delete all values with the given key prefix.
+	pub fn delete_prefix(&mut self, col: u32, prefix: &[u8]) {
+		self.transaction.delete_prefix(col, prefix)
+	}
+
+	/// Check the recorded reads against the current values returned by `get`.
+	///
+	/// Implementations must hold off any concurrent writes between this check
+	/// and writing the transaction.
+	pub fn validate<F>(&self, mut get: F) -> Result<()>
+	where
+		F: FnMut(u32, &[u8]) -> Result<Option<DBValue>>,
+	{
+		for read in &self.reads {
+			if get(read.col, &read.key)? != read.value {
+				return Err(Error::TransactionConflict(TransactionConflict { col: read.col, key: read.key.clone() }));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Value read by an `OptimisticTransaction` which has been changed by another write,
+/// see `Error::TransactionConflict`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionConflict {
+	/// Column of the changed value.
+	pub col: u32,
+	/// Key of the changed value.
+	pub key: DBKey,
+}
+
+impl fmt::Display for TransactionConflict {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Transaction conflict on key {:?} in column {}", &self.key[..], self.col)
+	}
+}
+
+impl error::Error for TransactionConflict {}
@@ -0,0 +1,376 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Staging of uncommitted writes on top of a database.
+
+use crate::{
+	is_after_start, is_before_end, DBOp, DBSnapshot, DBTransaction, DBValue, Error, IoStats, IoStatsKind, KeyValueDB,
+	OptimisticTransaction, Result,
+};
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+use std::{
+	cmp::Ordering,
+	collections::{BTreeMap, HashMap},
+	iter::Peekable,
+	ops::Bound,
+	sync::RwLock,
+};
+
+const POISONED_PROOF: &str = "the overlay lock is never held while panicking; qed";
+
+// Pending changes of a column.
+#[derive(Default, Clone, MallocSizeOf)]
+struct ColumnOverlay {
+	// prefixes deleted, before the changes below
+	deleted_prefixes: Vec<Vec<u8>>,
+	// inserted values, and deleted ones as `None`
+	changes: BTreeMap<Vec<u8>, Option<DBValue>>,
+}
+
+impl ColumnOverlay {
+	// `Some` if the overlay knows the value of the key, whether it is present or not.
+	fn get(&self, key: &[u8]) -> Option<Option<DBValue>> {
+		match self.changes.get(key) {
+			Some(value) => Some(value.clone()),
+			None if self.is_deleted(key) => Some(None),
+			None => None,
+		}
+	}
+
+	fn is_deleted(&self, key: &[u8]) -> bool {
+		self.deleted_prefixes.iter().any(|prefix| key.starts_with(prefix))
+	}
+
+	fn delete_prefix(&mut self, prefix: &[u8]) {
+		let deleted: Vec<Vec<u8>> = self.changes.keys().filter(|key| key.starts_with(prefix)).cloned().collect();
+		for key in deleted {
+			self.changes.remove(&key);
+		}
+		self.deleted_prefixes.push(prefix.to_vec());
+	}
+
+	// Changes of the keys within the bounds, and the deleted prefixes.
+	fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>, reverse: bool) -> Changes {
+		let mut changes: Vec<_> = self
+			.changes
+			.iter()
+			.filter(|(key, _)| is_after_start(key, start) && is_before_end(key, end))
+			.map(|(key, value)| (key.clone(), value.clone()))
+			.collect();
+		if reverse {
+			changes.reverse();
+		}
+		Changes { changes, deleted_prefixes: self.deleted_prefixes.clone(), reverse }
+	}
+}
+
+// Pending changes of a database, also used by the schema migrations to see the changes of their previous steps.
+#[derive(Default, Clone, MallocSizeOf)]
+pub(crate) struct Overlay {
+	columns: HashMap<u32, ColumnOverlay>,
+}
+
+impl Overlay {
+	fn get<DB: KeyValueDB + ?Sized>(&self, db: &DB, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		match self.columns.get(&col).and_then(|overlay| overlay.get(key)) {
+			Some(value) => Ok(value),
+			None => db.get(col, key),
+		}
+	}
+
+	pub(crate) fn apply(&mut self, transaction: DBTransaction) -> Result<()> {
+		for op in &transaction.ops {
+			match *op {
+				DBOp::Merge { col, .. } => {
+					return Err(Error::NotSupported(format!("merge into column {} of an overlay", col)))
+				}
+				DBOp::InsertWithExpiry { col, .. } => {
+					return Err(Error::NotSupported(format!("expiring insert into column {} of an overlay", col)))
+				}
+				_ => {}
+			}
+		}
+		for op in transaction.ops {
+			match op {
+				DBOp::Insert { col, key, value } => {
+					self.columns.entry(col).or_default().changes.insert(key.into_vec(), Some(value));
+				}
+				DBOp::Delete { col, key } => {
+					self.columns.entry(col).or_default().changes.insert(key.into_vec(), None);
+				}
+				DBOp::DeletePrefix { col, prefix } => self.columns.entry(col).or_default().delete_prefix(&prefix),
+				DBOp::Merge { .. } | DBOp::InsertWithExpiry { .. } => {
+					unreachable!("merge and expiring insert operations are rejected above; qed")
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub(crate) fn range(&self, col: u32, start: Bound<&[u8]>, end: Bound<&[u8]>, reverse: bool) -> Changes {
+		match self.columns.get(&col) {
+			Some(overlay) => overlay.range(start, end, reverse),
+			None => Changes { changes: Vec::new(), deleted_prefixes: Vec::new(), reverse },
+		}
+	}
+
+	fn prefix_range(&self, col: u32, prefix: &[u8]) -> Changes {
+		let end = crate::end_prefix(prefix);
+		let end = end.as_ref().map_or(Bound::Unbounded, |end| Bound::Excluded(&end[..]));
+		self.range(col, Bound::Included(prefix), end, false)
+	}
+
+	fn into_transaction(self) -> DBTransaction {
+		let mut transaction = DBTransaction::new();
+		let mut columns: Vec<_> = self.columns.into_iter().collect();
+		columns.sort_by_key(|(col, _)| *col);
+		for (col, overlay) in columns {
+			for prefix in overlay.deleted_prefixes {
+				transaction.delete_prefix(col, &prefix);
+			}
+			for (key, value) in overlay.changes {
+				match value {
+					Some(value) => transaction.put_vec(col, &key, value),
+					None => transaction.delete(col, &key),
+				}
+			}
+		}
+		transaction
+	}
+}
+
+// Pending changes of a range of keys, in iteration order.
+pub(crate) struct Changes {
+	changes: Vec<(Vec<u8>, Option<DBValue>)>,
+	deleted_prefixes: Vec<Vec<u8>>,
+	reverse: bool,
+}
+
+impl Changes {
+	// Merge the changes into an iterator over the committed keys in the same range and order.
+	pub(crate) fn merge<'a>(
+		self,
+		committed: Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		if self.changes.is_empty() && self.deleted_prefixes.is_empty() {
+			return committed;
+		}
+		let deleted_prefixes = self.deleted_prefixes;
+		let committed =
+			committed.filter(move |(key, _)| !deleted_prefixes.iter().any(|prefix| key.starts_with(prefix)));
+		Box::new(MergeIter {
+			committed: committed.peekable(),
+			changes: self.changes.into_iter().peekable(),
+			reverse: self.reverse,
+		})
+	}
+}
+
+struct MergeIter<C: Iterator, P: Iterator> {
+	committed: Peekable<C>,
+	changes: Peekable<P>,
+	reverse: bool,
+}
+
+impl<C, P> Iterator for MergeIter<C, P>
+where
+	C: Iterator<Item = (Box<[u8]>, Box<[u8]>)>,
+	P: Iterator<Item = (Vec<u8>, Option<DBValue>)>,
+{
+	type Item = (Box<[u8]>, Box<[u8]>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let order = match (self.committed.peek(), self.changes.peek()) {
+				(Some((committed, _)), Some((changed, _))) => {
+					let order = committed[..].cmp(&changed[..]);
+					if self.reverse {
+						order.reverse()
+					} else {
+						order
+					}
+				}
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(None, None) => return None,
+			};
+			if order == Ordering::Less {
+				return self.committed.next();
+			}
+			if order == Ordering::Equal {
+				// the change overrides the committed value
+				self.committed.next();
+			}
+			match self.changes.next() {
+				Some((key, Some(value))) => return Some((key.into_boxed_slice(), value.into_boxed_slice())),
+				Some((_, None)) => continue,
+				None => unreachable!("a change was peeked above; qed"),
+			}
+		}
+	}
+}
+
+/// Database wrapper staging the writes made through it until they are committed.
+///
+/// The staged inserts and deletes, including prefix deletes, are visible to the reads and the
+/// iterators of the wrapper, but not to the inner database until `commit` writes them all in a
+/// single transaction. Merge operations and expiring inserts cannot be staged, as their result
+/// depends on the merge operator and the time to live of the inner database.
+pub struct OverlayDB<DB> {
+	db: DB,
+	overlay: RwLock<Overlay>,
+}
+
+impl<DB: KeyValueDB> OverlayDB<DB> {
+	/// Wrap the database, without any staged change.
+	pub fn new(db: DB) -> Self {
+		OverlayDB { db, overlay: RwLock::new(Overlay::default()) }
+	}
+
+	/// Get a reference to the inner database.
+	pub fn inner(&self) -> &DB {
+		&self.db
+	}
+
+	/// Unwrap the inner database, discarding the staged changes.
+	pub fn into_inner(self) -> DB {
+		self.db
+	}
+
+	/// Whether there are staged changes.
+	pub fn is_dirty(&self) -> bool {
+		!self.overlay.read().expect(POISONED_PROOF).columns.is_empty()
+	}
+
+	/// The staged changes, as a transaction to write to the inner database.
+	pub fn pending(&self) -> DBTransaction {
+		self.overlay.read().expect(POISONED_PROOF).clone().into_transaction()
+	}
+
+	/// Write the staged changes to the inner database in a single transaction.
+	///
+	/// Errors of the staged writes, such as unknown columns, are only reported here, and on error
+	/// the changes remain staged.
+	pub fn commit(&self) -> Result<()> {
+		let mut overlay = self.overlay.write().expect(POISONED_PROOF);
+		self.db.write(overlay.clone().into_transaction())?;
+		*overlay = Overlay::default();
+		Ok(())
+	}
+
+	/// Discard the staged changes.
+	pub fn discard(&self) {
+		*self.overlay.write().expect(POISONED_PROOF) = Overlay::default();
+	}
+}
+
+impl<DB: MallocSizeOf> MallocSizeOf for OverlayDB<DB> {
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		self.db.size_of(ops) + self.overlay.size_of(ops)
+	}
+}
+
+impl<DB: KeyValueDB> KeyValueDB for OverlayDB<DB> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		self.overlay.read().expect(POISONED_PROOF).get(&self.db, col, key)
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		self.iter_with_prefix(col, prefix).next().map(|(_, value)| value)
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		self.overlay.write().expect(POISONED_PROOF).apply(transaction)
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		let mut overlay = self.overlay.write().expect(POISONED_PROOF);
+		transaction.validate(|col, key| overlay.get(&self.db, col, key))?;
+		overlay.apply(transaction.transaction)
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let changes = self.overlay.read().expect(POISONED_PROOF).range(col, Bound::Unbounded, Bound::Unbounded, false);
+		changes.merge(self.db.iter(col))
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let changes = self.overlay.read().expect(POISONED_PROOF).prefix_range(col, prefix);
+		changes.merge(self.db.iter_with_prefix(col, prefix))
+	}
+
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let changes = self.overlay.read().expect(POISONED_PROOF).range(col, start, end, false);
+		changes.merge(self.db.iter_range(col, start, end))
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let changes = self.overlay.read().expect(POISONED_PROOF).range(col, start, end, true);
+		changes.merge(self.db.iter_range_rev(col, start, end))
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		let overlay = self.overlay.read().expect(POISONED_PROOF);
+		Box::new(OverlaySnapshot { snapshot: self.db.snapshot(), overlay: overlay.clone() })
+	}
+
+	fn restore(&self, new_db: &str) -> Result<()> {
+		let mut overlay = self.overlay.write().expect(POISONED_PROOF);
+		self.db.restore(new_db)?;
+		*overlay = Overlay::default();
+		Ok(())
+	}
+
+	fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+		self.db.io_stats(kind)
+	}
+
+	fn compact_expired(&self) -> Result<()> {
+		self.db.compact_expired()
+	}
+}
+
+struct OverlaySnapshot<'a> {
+	snapshot: Box<dyn DBSnapshot + 'a>,
+	overlay: Overlay,
+}
+
+impl<'a> DBSnapshot for OverlaySnapshot<'a> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		match self.overlay.columns.get(&col).and_then(|overlay| overlay.get(key)) {
+			Some(value) => Ok(value),
+			None => self.snapshot.get(col, key),
+		}
+	}
+
+	fn iter<'b>(&'b self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b> {
+		self.overlay.range(col, Bound::Unbounded, Bound::Unbounded, false).merge(self.snapshot.iter(col))
+	}
+
+	fn iter_with_prefix<'b>(
+		&'b self,
+		col: u32,
+		prefix: &'b [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b> {
+		self.overlay.prefix_range(col, prefix).merge(self.snapshot.iter_with_prefix(col, prefix))
+	}
+}
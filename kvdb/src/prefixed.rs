@@ -0,0 +1,364 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Views of a database scoped to a key prefix.
+
+use crate::{
+	end_prefix, DBKey, DBOp, DBRead, DBSnapshot, DBTransaction, DBValue, Error, IoStats, IoStatsKind, KeyValueDB,
+	OptimisticTransaction, Result,
+};
+use owning_ref::OwningHandle;
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+use std::{
+	ops::Bound,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+	time::Instant,
+};
+
+#[derive(Default)]
+struct Counters {
+	transactions: AtomicU64,
+	reads: AtomicU64,
+	writes: AtomicU64,
+	bytes_read: AtomicU64,
+	bytes_written: AtomicU64,
+}
+
+impl Counters {
+	fn tally_read(&self, key: &[u8], value: &Option<DBValue>) {
+		self.reads.fetch_add(1, Ordering::Relaxed);
+		self.bytes_read.fetch_add((key.len() + value.as_ref().map_or(0, |v| v.len())) as u64, Ordering::Relaxed);
+	}
+
+	fn tally_transaction(&self, transaction: &DBTransaction) {
+		self.transactions.fetch_add(1, Ordering::Relaxed);
+		for op in &transaction.ops {
+			let bytes = match *op {
+				DBOp::Insert { ref key, ref value, .. } | DBOp::InsertWithExpiry { ref key, ref value, .. } => {
+					key.len() + value.len()
+				}
+				DBOp::Merge { ref key, ref operand, .. } => key.len() + operand.len(),
+				DBOp::Delete { ref key, .. } => key.len(),
+				DBOp::DeletePrefix { ref prefix, .. } => prefix.len(),
+			};
+			self.writes.fetch_add(1, Ordering::Relaxed);
+			self.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+		}
+	}
+
+	// Add the counts to the statistics, resetting them if `reset` is set.
+	fn report(&self, stats: &mut IoStats, reset: bool) {
+		let take = |counter: &AtomicU64| {
+			if reset {
+				counter.swap(0, Ordering::Relaxed)
+			} else {
+				counter.load(Ordering::Relaxed)
+			}
+		};
+		stats.transactions += take(&self.transactions);
+		stats.reads += take(&self.reads);
+		stats.writes += take(&self.writes);
+		stats.bytes_read += take(&self.bytes_read);
+		stats.bytes_written += take(&self.bytes_written);
+	}
+}
+
+struct ViewStats {
+	overall: Counters,
+	since_previous: Counters,
+	started: Instant,
+	previous_taken: Mutex<Instant>,
+}
+
+impl ViewStats {
+	fn new() -> Self {
+		let now = Instant::now();
+		ViewStats {
+			overall: Counters::default(),
+			since_previous: Counters::default(),
+			started: now,
+			previous_taken: Mutex::new(now),
+		}
+	}
+
+	fn tally_read(&self, key: &[u8], value: &Option<DBValue>) {
+		self.overall.tally_read(key, value);
+		self.since_previous.tally_read(key, value);
+	}
+
+	fn tally_transaction(&self, transaction: &DBTransaction) {
+		self.overall.tally_transaction(transaction);
+		self.since_previous.tally_transaction(transaction);
+	}
+
+	fn take(&self, kind: IoStatsKind) -> IoStats {
+		let mut stats = IoStats::empty();
+		let now = Instant::now();
+		match kind {
+			IoStatsKind::Overall => {
+				self.overall.report(&mut stats, false);
+				stats.started = self.started;
+			}
+			IoStatsKind::SincePrevious => {
+				let mut previous_taken =
+					self.previous_taken.lock().expect("the lock is never held while panicking; qed");
+				self.since_previous.report(&mut stats, true);
+				stats.started = std::mem::replace(&mut *previous_taken, now);
+			}
+		}
+		stats.span = now.duration_since(stats.started);
+		stats
+	}
+}
+
+/// View of the keys of a database starting with a given prefix, as a database of its own.
+///
+/// Keys are prefixed on the way in and stripped on the way out, so several views with
+/// distinct prefixes, none of which prefixing another, can share a database without
+/// seeing each other's keys. Statistics only cover the reads and writes made through the view.
+pub struct PrefixedDB<DB> {
+	db: Arc<DB>,
+	prefix: Vec<u8>,
+	stats: ViewStats,
+}
+
+impl<DB: KeyValueDB> PrefixedDB<DB> {
+	/// Create a view of the keys of the database starting with `prefix`.
+	pub fn new(db: Arc<DB>, prefix: &[u8]) -> Self {
+		PrefixedDB { db, prefix: prefix.to_vec(), stats: ViewStats::new() }
+	}
+
+	/// Get a reference to the parent database.
+	pub fn inner(&self) -> &Arc<DB> {
+		&self.db
+	}
+
+	/// The prefix of the keys of the view.
+	pub fn prefix(&self) -> &[u8] {
+		&self.prefix
+	}
+
+	fn key(&self, key: &[u8]) -> DBKey {
+		let mut prefixed = DBKey::from_slice(&self.prefix);
+		prefixed.extend_from_slice(key);
+		prefixed
+	}
+
+	// The bounds of the keys of the parent database within the given bounds of the view.
+	fn bounds(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+		let start = match start {
+			Bound::Included(key) => Bound::Included(self.key(key).to_vec()),
+			Bound::Excluded(key) => Bound::Excluded(self.key(key).to_vec()),
+			Bound::Unbounded => Bound::Included(self.prefix.clone()),
+		};
+		let end = match end {
+			Bound::Included(key) => Bound::Included(self.key(key).to_vec()),
+			Bound::Excluded(key) => Bound::Excluded(self.key(key).to_vec()),
+			Bound::Unbounded => end_prefix(&self.prefix).map_or(Bound::Unbounded, Bound::Excluded),
+		};
+		(start, end)
+	}
+
+	fn transaction(&self, transaction: &DBTransaction) -> DBTransaction {
+		let ops = transaction
+			.ops
+			.iter()
+			.map(|op| match *op {
+				DBOp::Insert { col, ref key, ref value } => {
+					DBOp::Insert { col, key: self.key(key), value: value.clone() }
+				}
+				DBOp::Delete { col, ref key } => DBOp::Delete { col, key: self.key(key) },
+				DBOp::Merge { col, ref key, ref operand } => {
+					DBOp::Merge { col, key: self.key(key), operand: operand.clone() }
+				}
+				DBOp::DeletePrefix { col, ref prefix } => DBOp::DeletePrefix { col, prefix: self.key(prefix) },
+				DBOp::InsertWithExpiry { col, ref key, ref value, expiry } => {
+					DBOp::InsertWithExpiry { col, key: self.key(key), value: value.clone(), expiry }
+				}
+			})
+			.collect();
+		DBTransaction { ops }
+	}
+}
+
+type KeyValueIter<'a> = Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+
+// Strip the prefix of the keys of the view.
+fn strip(prefix_len: usize, iter: KeyValueIter<'_>) -> KeyValueIter<'_> {
+	Box::new(iter.map(move |(key, value)| (key[prefix_len..].to_vec().into_boxed_slice(), value)))
+}
+
+// Iterator of the parent database borrowing the prefixed keys it was created with,
+// which it owns since the caller only lends the keys of the view.
+struct OwningIter<'a, T> {
+	inner: OwningHandle<Box<T>, KeyValueIter<'a>>,
+}
+
+impl<'a, T: 'a> OwningIter<'a, T> {
+	fn boxed(keys: T, iter: impl FnOnce(&'a T) -> KeyValueIter<'a>) -> KeyValueIter<'a> {
+		let inner = OwningHandle::new_with_fn(Box::new(keys), move |keys| {
+			// the keys are boxed, so they stay in place until the handle drops the iterator; qed
+			let keys = unsafe { keys.as_ref().expect("initialized as non-null; qed") };
+			iter(keys)
+		});
+		Box::new(OwningIter { inner })
+	}
+}
+
+impl<'a, T> Iterator for OwningIter<'a, T> {
+	type Item = (Box<[u8]>, Box<[u8]>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next()
+	}
+}
+
+// Borrow the key of an owned bound.
+fn as_ref(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+	match *bound {
+		Bound::Included(ref key) => Bound::Included(key),
+		Bound::Excluded(ref key) => Bound::Excluded(key),
+		Bound::Unbounded => Bound::Unbounded,
+	}
+}
+
+impl<DB: MallocSizeOf> MallocSizeOf for PrefixedDB<DB> {
+	// the parent database is shared between the views, so it is not accounted for
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		self.prefix.size_of(ops)
+	}
+}
+
+impl<DB: KeyValueDB> KeyValueDB for PrefixedDB<DB> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		let value = self.db.get(col, &self.key(key))?;
+		self.stats.tally_read(key, &value);
+		Ok(value)
+	}
+
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<Result<Option<DBValue>>> {
+		let prefixed: Vec<DBKey> = keys.iter().map(|key| self.key(key)).collect();
+		let prefixed: Vec<&[u8]> = prefixed.iter().map(|key| &key[..]).collect();
+		let values = self.db.get_many(col, &prefixed);
+		for (key, value) in keys.iter().zip(&values) {
+			if let Ok(value) = value {
+				self.stats.tally_read(key, value);
+			}
+		}
+		values
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		self.db.get_by_prefix(col, &self.key(prefix))
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		self.db.write(self.transaction(&transaction))?;
+		self.stats.tally_transaction(&transaction);
+		Ok(())
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		let OptimisticTransaction { reads, transaction } = transaction;
+		let reads =
+			reads.into_iter().map(|DBRead { col, key, value }| DBRead { col, key: self.key(&key), value }).collect();
+		let prefixed = OptimisticTransaction { reads, transaction: self.transaction(&transaction) };
+		self.db.write_optimistic(prefixed).map_err(|err| match err {
+			Error::TransactionConflict(mut conflict) => {
+				conflict.key = DBKey::from_slice(&conflict.key[self.prefix.len()..]);
+				Error::TransactionConflict(conflict)
+			}
+			err => err,
+		})?;
+		self.stats.tally_transaction(&transaction);
+		Ok(())
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		strip(self.prefix.len(), self.db.iter_with_prefix(col, &self.prefix))
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let prefix_len = self.prefix.len();
+		OwningIter::boxed(self.key(prefix), move |prefix| strip(prefix_len, self.db.iter_with_prefix(col, prefix)))
+	}
+
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let prefix_len = self.prefix.len();
+		OwningIter::boxed(self.bounds(start, end), move |(start, end)| {
+			strip(prefix_len, self.db.iter_range(col, as_ref(start), as_ref(end)))
+		})
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		let prefix_len = self.prefix.len();
+		OwningIter::boxed(self.bounds(start, end), move |(start, end)| {
+			strip(prefix_len, self.db.iter_range_rev(col, as_ref(start), as_ref(end)))
+		})
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		Box::new(PrefixedSnapshot { snapshot: self.db.snapshot(), prefix: &self.prefix })
+	}
+
+	fn restore(&self, _new_db: &str) -> Result<()> {
+		Err(Error::NotSupported("restoring a prefixed view of a database".into()))
+	}
+
+	fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+		self.stats.take(kind)
+	}
+
+	// the values of the other views expire as well
+	fn compact_expired(&self) -> Result<()> {
+		self.db.compact_expired()
+	}
+}
+
+struct PrefixedSnapshot<'a> {
+	snapshot: Box<dyn DBSnapshot + 'a>,
+	prefix: &'a [u8],
+}
+
+impl<'a> DBSnapshot for PrefixedSnapshot<'a> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		self.snapshot.get(col, &[self.prefix, key].concat())
+	}
+
+	fn iter<'b>(&'b self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b> {
+		strip(self.prefix.len(), self.snapshot.iter_with_prefix(col, self.prefix))
+	}
+
+	fn iter_with_prefix<'b>(
+		&'b self,
+		col: u32,
+		prefix: &'b [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'b> {
+		let prefix_len = self.prefix.len();
+		OwningIter::boxed([self.prefix, prefix].concat(), move |prefix| {
+			strip(prefix_len, self.snapshot.iter_with_prefix(col, prefix))
+		})
+	}
+}
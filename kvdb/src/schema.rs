@@ -0,0 +1,256 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Named columns and versioned schema migrations.
+
+use crate::{overlay::Overlay, DBTransaction, DBValue, Error, KeyValueDB, Result};
+use std::{collections::BTreeMap, fmt, ops::Bound};
+
+// Keys of the metadata column.
+const VERSION_KEY: &[u8] = b"schema/version";
+const COLUMN_PREFIX: &[u8] = b"schema/column/";
+
+type Rewrite = Box<dyn Fn(&[u8], &[u8]) -> Option<DBValue> + Send + Sync>;
+
+/// A step of a `Migration`.
+pub enum MigrationStep {
+	/// Name a column which is not in use yet.
+	AddColumn(String),
+	/// Delete all the data of a column and free its name.
+	DropColumn(String),
+	/// Rename a column, keeping its data.
+	RenameColumn {
+		/// Current name of the column.
+		from: String,
+		/// New name of the column.
+		to: String,
+	},
+	/// Rewrite every value of a column. The closure is given the key and the value,
+	/// and returns the new value or `None` to delete it.
+	RewriteValues(String, Rewrite),
+}
+
+impl fmt::Debug for MigrationStep {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			MigrationStep::AddColumn(ref name) => f.debug_tuple("AddColumn").field(name).finish(),
+			MigrationStep::DropColumn(ref name) => f.debug_tuple("DropColumn").field(name).finish(),
+			MigrationStep::RenameColumn { ref from, ref to } => {
+				f.debug_struct("RenameColumn").field("from", from).field("to", to).finish()
+			}
+			MigrationStep::RewriteValues(ref name, _) => f.debug_tuple("RewriteValues").field(name).finish(),
+		}
+	}
+}
+
+/// Steps upgrading the schema of a database to a given version.
+///
+/// The steps are applied in order, each seeing the columns and the data as left by the previous ones.
+#[derive(Debug)]
+pub struct Migration {
+	version: u32,
+	steps: Vec<MigrationStep>,
+}
+
+impl Migration {
+	/// Create a migration to the given version, without any step.
+	pub fn new(version: u32) -> Self {
+		Migration { version, steps: Vec::new() }
+	}
+
+	/// Version of the schema after the migration.
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	/// Add a step to the migration.
+	pub fn step(mut self, step: MigrationStep) -> Self {
+		self.steps.push(step);
+		self
+	}
+
+	/// Name a column which is not in use yet.
+	pub fn add_column(self, name: &str) -> Self {
+		self.step(MigrationStep::AddColumn(name.into()))
+	}
+
+	/// Delete all the data of a column and free its name.
+	pub fn drop_column(self, name: &str) -> Self {
+		self.step(MigrationStep::DropColumn(name.into()))
+	}
+
+	/// Rename a column, keeping its data.
+	pub fn rename_column(self, from: &str, to: &str) -> Self {
+		self.step(MigrationStep::RenameColumn { from: from.into(), to: to.into() })
+	}
+
+	/// Rewrite every value of a column, or delete it if the closure returns `None`.
+	pub fn rewrite_values<F>(self, name: &str, rewrite: F) -> Self
+	where
+		F: Fn(&[u8], &[u8]) -> Option<DBValue> + Send + Sync + 'static,
+	{
+		self.step(MigrationStep::RewriteValues(name.into(), Box::new(rewrite)))
+	}
+}
+
+/// Registry of the named columns of a database and of its schema version.
+///
+/// The registry is stored in a metadata column, which must not be used for anything else.
+/// Columns are named by migrations, which pick the lowest column index not in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+	meta_col: u32,
+	version: u32,
+	columns: BTreeMap<String, u32>,
+}
+
+impl Schema {
+	/// Load the schema stored in the metadata column. A database without a schema has version 0.
+	pub fn load<DB: KeyValueDB + ?Sized>(db: &DB, meta_col: u32) -> Result<Schema> {
+		let version = match db.get(meta_col, VERSION_KEY)? {
+			Some(value) => decode_u32(&value).ok_or_else(|| Error::Corruption("invalid schema version".into()))?,
+			None => 0,
+		};
+		let mut columns = BTreeMap::new();
+		for (key, value) in db.iter_with_prefix(meta_col, COLUMN_PREFIX) {
+			let name = String::from_utf8(key[COLUMN_PREFIX.len()..].to_vec())
+				.map_err(|_| Error::Corruption("invalid column name".into()))?;
+			let col = decode_u32(&value).ok_or_else(|| Error::Corruption(format!("invalid column of {}", name)))?;
+			columns.insert(name, col);
+		}
+		Ok(Schema { meta_col, version, columns })
+	}
+
+	/// The schema version.
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	/// The metadata column.
+	pub fn meta_col(&self) -> u32 {
+		self.meta_col
+	}
+
+	/// The index of the column with the given name.
+	pub fn column(&self, name: &str) -> Option<u32> {
+		self.columns.get(name).cloned()
+	}
+
+	/// The named columns and their indices, by name.
+	pub fn columns(&self) -> impl Iterator<Item = (&str, u32)> {
+		self.columns.iter().map(|(name, col)| (name.as_str(), *col))
+	}
+
+	/// Apply the migrations to a version above the current one, in order.
+	///
+	/// Each migration is written in a single transaction along with the new schema, so a
+	/// failed migration leaves the database and the schema as of the previous one.
+	/// Only the columns below `num_columns` may be named.
+	pub fn migrate<DB: KeyValueDB + ?Sized>(
+		&mut self,
+		db: &DB,
+		num_columns: u32,
+		migrations: &[Migration],
+	) -> Result<()> {
+		if let Some(pair) = migrations.windows(2).find(|pair| pair[0].version >= pair[1].version) {
+			return Err(Error::InvalidMigration(format!(
+				"version {} follows version {}",
+				pair[1].version, pair[0].version
+			)));
+		}
+		for migration in migrations {
+			if migration.version <= self.version {
+				continue;
+			}
+			let mut schema = self.clone();
+			let mut transaction = DBTransaction::new();
+			schema.apply(db, num_columns, migration, &mut transaction)?;
+			db.write(transaction)?;
+			*self = schema;
+		}
+		Ok(())
+	}
+
+	fn apply<DB: KeyValueDB + ?Sized>(
+		&mut self,
+		db: &DB,
+		num_columns: u32,
+		migration: &Migration,
+		transaction: &mut DBTransaction,
+	) -> Result<()> {
+		for step in &migration.steps {
+			match *step {
+				MigrationStep::AddColumn(ref name) => {
+					self.check_free(name)?;
+					let col = (0..num_columns)
+						.find(|col| *col != self.meta_col && self.columns.values().all(|used| used != col))
+						.ok_or_else(|| Error::InvalidMigration(format!("no column left for {}", name)))?;
+					self.columns.insert(name.clone(), col);
+					transaction.put(self.meta_col, &column_key(name), &col.to_le_bytes());
+				}
+				MigrationStep::DropColumn(ref name) => {
+					let col = self.column_of(name)?;
+					self.columns.remove(name);
+					transaction.delete_prefix(col, &[]);
+					transaction.delete(self.meta_col, &column_key(name));
+				}
+				MigrationStep::RenameColumn { ref from, ref to } => {
+					let col = self.column_of(from)?;
+					self.check_free(to)?;
+					self.columns.remove(from);
+					self.columns.insert(to.clone(), col);
+					transaction.delete(self.meta_col, &column_key(from));
+					transaction.put(self.meta_col, &column_key(to), &col.to_le_bytes());
+				}
+				MigrationStep::RewriteValues(ref name, ref rewrite) => {
+					let col = self.column_of(name)?;
+					// the values as changed by the previous steps
+					let mut pending = Overlay::default();
+					pending.apply(transaction.clone())?;
+					let values = pending.range(col, Bound::Unbounded, Bound::Unbounded, false).merge(db.iter(col));
+					for (key, value) in values {
+						match rewrite(&key, &value) {
+							Some(ref new_value) if *new_value == *value => {}
+							Some(new_value) => transaction.put_vec(col, &key, new_value),
+							None => transaction.delete(col, &key),
+						}
+					}
+				}
+			}
+		}
+		self.version = migration.version;
+		transaction.put(self.meta_col, VERSION_KEY, &self.version.to_le_bytes());
+		Ok(())
+	}
+
+	fn column_of(&self, name: &str) -> Result<u32> {
+		self.column(name).ok_or_else(|| Error::InvalidMigration(format!("no column named {}", name)))
+	}
+
+	fn check_free(&self, name: &str) -> Result<()> {
+		if self.columns.contains_key(name) {
+			return Err(Error::InvalidMigration(format!("column {} already exists", name)));
+		}
+		Ok(())
+	}
+}
+
+fn column_key(name: &str) -> Vec<u8> {
+	let mut key = COLUMN_PREFIX.to_vec();
+	key.extend_from_slice(name.as_bytes());
+	key
+}
+
+fn decode_u32(bytes: &[u8]) -> Option<u32> {
+	if bytes.len() != 4 {
+		return None;
+	}
+	let mut buf = [0u8; 4];
+	buf.copy_from_slice(bytes);
+	Some(u32::from_le_bytes(buf))
+}
@@ -0,0 +1,256 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Notifications of the changes written to a database.
+
+use crate::{
+	DBKey, DBOp, DBSnapshot, DBTransaction, DBValue, IoStats, IoStatsKind, KeyValueDB, OptimisticTransaction, Result,
+};
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+use std::{
+	ops::Bound,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Mutex, MutexGuard, RwLock,
+	},
+};
+
+/// Selects the changes a listener is notified of.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionFilter {
+	/// All changes.
+	All,
+	/// Changes to the given column.
+	Column(u32),
+	/// Changes to the keys of the given column starting with the given prefix.
+	Prefix(u32, DBKey),
+}
+
+impl SubscriptionFilter {
+	/// Whether the operation may change a value selected by the filter.
+	pub fn matches(&self, op: &DBOp) -> bool {
+		match *self {
+			SubscriptionFilter::All => true,
+			SubscriptionFilter::Column(col) => op.col() == col,
+			SubscriptionFilter::Prefix(col, ref prefix) => {
+				op.col() == col
+					&& match *op {
+						// the deleted range overlaps the prefix if one prefix contains the other
+						DBOp::DeletePrefix { prefix: ref deleted, .. } => {
+							deleted.starts_with(prefix) || prefix.starts_with(deleted)
+						}
+						_ => op.key().starts_with(prefix),
+					}
+			}
+		}
+	}
+}
+
+/// Identifies a listener registered with `SubscribableDB::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+type Listener = Box<dyn Fn(&[DBOp]) + Send + Sync>;
+
+const POISONED_PROOF: &str = "the listeners lock is never held for writing while calling listeners; qed";
+
+/// Database wrapper notifying listeners of the changes written through it.
+///
+/// Listeners are called with the committed operations matching their filter right after
+/// each successful `write` or `write_optimistic`, on the writing thread, in the order the writes
+/// are committed. Listeners must not subscribe, unsubscribe or write through the wrapper themselves.
+/// Writes made to the inner database directly are not noticed.
+pub struct SubscribableDB<DB> {
+	db: DB,
+	listeners: RwLock<Vec<(SubscriptionId, SubscriptionFilter, Listener)>>,
+	// serializes the writes, so that the listeners are notified in the order they are committed
+	write_lock: Mutex<()>,
+	next_id: AtomicUsize,
+}
+
+impl<DB: KeyValueDB> SubscribableDB<DB> {
+	/// Wrap the database.
+	pub fn new(db: DB) -> Self {
+		SubscribableDB {
+			db,
+			listeners: RwLock::new(Vec::new()),
+			write_lock: Mutex::new(()),
+			next_id: AtomicUsize::new(0),
+		}
+	}
+
+	/// Get a reference to the inner database.
+	pub fn inner(&self) -> &DB {
+		&self.db
+	}
+
+	/// Unwrap the inner database.
+	pub fn into_inner(self) -> DB {
+		self.db
+	}
+
+	/// Register a listener for the changes matching the filter.
+	pub fn subscribe<F>(&self, filter: SubscriptionFilter, listener: F) -> SubscriptionId
+	where
+		F: Fn(&[DBOp]) + Send + Sync + 'static,
+	{
+		let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+		self.listeners.write().expect(POISONED_PROOF).push((id, filter, Box::new(listener)));
+		id
+	}
+
+	/// Remove a listener. Returns `false` if it was not registered.
+	pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+		let mut listeners = self.listeners.write().expect(POISONED_PROOF);
+		let len = listeners.len();
+		listeners.retain(|(listener_id, _, _)| *listener_id != id);
+		listeners.len() != len
+	}
+
+	// The lock guards no data, so a listener panicking while it is held leaves nothing inconsistent.
+	fn lock_writes(&self) -> MutexGuard<'_, ()> {
+		self.write_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	// Copy of the operations to notify the listeners of, if there are any listeners.
+	fn pending_ops(&self, transaction: &DBTransaction) -> Option<Vec<DBOp>> {
+		let listeners = self.listeners.read().expect(POISONED_PROOF);
+		if listeners.is_empty() {
+			None
+		} else {
+			Some(transaction.ops.clone())
+		}
+	}
+
+	fn notify(&self, ops: &[DBOp]) {
+		let listeners = self.listeners.read().expect(POISONED_PROOF);
+		for (_, filter, listener) in listeners.iter() {
+			let matching: Vec<DBOp> = ops.iter().filter(|op| filter.matches(op)).cloned().collect();
+			if !matching.is_empty() {
+				listener(&matching);
+			}
+		}
+	}
+}
+
+impl<DB: MallocSizeOf> MallocSizeOf for SubscribableDB<DB> {
+	fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+		self.db.size_of(ops)
+	}
+}
+
+impl<DB: KeyValueDB> KeyValueDB for SubscribableDB<DB> {
+	fn get(&self, col: u32, key: &[u8]) -> Result<Option<DBValue>> {
+		self.db.get(col, key)
+	}
+
+	fn get_many(&self, col: u32, keys: &[&[u8]]) -> Vec<Result<Option<DBValue>>> {
+		self.db.get_many(col, keys)
+	}
+
+	fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+		self.db.get_by_prefix(col, prefix)
+	}
+
+	fn write(&self, transaction: DBTransaction) -> Result<()> {
+		let _write_lock = self.lock_writes();
+		let ops = self.pending_ops(&transaction);
+		self.db.write(transaction)?;
+		if let Some(ops) = ops {
+			self.notify(&ops);
+		}
+		Ok(())
+	}
+
+	fn write_optimistic(&self, transaction: OptimisticTransaction) -> Result<()> {
+		let _write_lock = self.lock_writes();
+		let ops = self.pending_ops(&transaction.transaction);
+		self.db.write_optimistic(transaction)?;
+		if let Some(ops) = ops {
+			self.notify(&ops);
+		}
+		Ok(())
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter(col)
+	}
+
+	fn iter_with_prefix<'a>(
+		&'a self,
+		col: u32,
+		prefix: &'a [u8],
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter_with_prefix(col, prefix)
+	}
+
+	fn iter_range<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter_range(col, start, end)
+	}
+
+	fn iter_range_rev<'a>(
+		&'a self,
+		col: u32,
+		start: Bound<&'a [u8]>,
+		end: Bound<&'a [u8]>,
+	) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.db.iter_range_rev(col, start, end)
+	}
+
+	fn snapshot<'a>(&'a self) -> Box<dyn DBSnapshot + 'a> {
+		self.db.snapshot()
+	}
+
+	fn restore(&self, new_db: &str) -> Result<()> {
+		self.db.restore(new_db)
+	}
+
+	fn io_stats(&self, kind: IoStatsKind) -> IoStats {
+		self.db.io_stats(kind)
+	}
+
+	fn compact_expired(&self) -> Result<()> {
+		self.db.compact_expired()
+	}
+
+	fn has_key(&self, col: u32, key: &[u8]) -> Result<bool> {
+		self.db.has_key(col, key)
+	}
+
+	fn has_prefix(&self, col: u32, prefix: &[u8]) -> bool {
+		self.db.has_prefix(col, prefix)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::SubscriptionFilter;
+	use crate::{DBKey, DBOp};
+
+	#[test]
+	fn filter_matches() {
+		let insert = DBOp::Insert { col: 1, key: DBKey::from_slice(b"abc"), value: b"value".to_vec() };
+		let delete_prefix = DBOp::DeletePrefix { col: 1, prefix: DBKey::from_slice(b"a") };
+
+		assert!(SubscriptionFilter::All.matches(&insert));
+		assert!(SubscriptionFilter::Column(1).matches(&insert));
+		assert!(!SubscriptionFilter::Column(0).matches(&insert));
+		assert!(SubscriptionFilter::Prefix(1, DBKey::from_slice(b"ab")).matches(&insert));
+		assert!(!SubscriptionFilter::Prefix(1, DBKey::from_slice(b"b")).matches(&insert));
+		assert!(!SubscriptionFilter::Prefix(0, DBKey::from_slice(b"ab")).matches(&insert));
+
+		assert!(SubscriptionFilter::Prefix(1, DBKey::from_slice(b"ab")).matches(&delete_prefix));
+		assert!(SubscriptionFilter::Prefix(1, DBKey::from_slice(b"")).matches(&delete_prefix));
+		assert!(!SubscriptionFilter::Prefix(1, DBKey::from_slice(b"b")).matches(&delete_prefix));
+	}
+}
@@ -0,0 +1,59 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `ThreadPoolDB` tests.
+
+#![cfg(feature = "thread-pool")]
+
+use futures::{
+	executor::{block_on, ThreadPool},
+	stream::StreamExt,
+};
+use kvdb::{AsyncKeyValueDB, KeyValueDB, ThreadPoolDB};
+use kvdb_memorydb::create;
+
+#[test]
+fn runs_calls_on_the_pool() {
+	let db = ThreadPoolDB::new(create(1), ThreadPool::new().unwrap());
+	block_on(async {
+		let mut batch = db.inner().transaction();
+		batch.put(0, b"key1", b"horse");
+		batch.put(0, b"key2", b"pigeon");
+		batch.put(0, b"other", b"cat");
+		db.write(batch).await.unwrap();
+		assert_eq!(db.get(0, b"key1").await.unwrap().unwrap(), b"horse");
+		assert!(db.get(0, b"key3").await.unwrap().is_none());
+		assert_eq!(db.iter(0).collect::<Vec<_>>().await.len(), 3);
+		let keys: Vec<_> = db.iter_with_prefix(0, b"key").map(|(key, _)| key).collect().await;
+		assert_eq!(keys, vec![b"key1".to_vec().into_boxed_slice(), b"key2".to_vec().into_boxed_slice()]);
+		// dropping a stream stops its iteration
+		assert!(db.iter(0).next().await.is_some());
+	});
+}
+
+#[test]
+fn streams_do_not_hold_the_pool() {
+	let pool = ThreadPool::builder().pool_size(1).create().unwrap();
+	let db = ThreadPoolDB::new(create(1), pool);
+	let mut batch = db.inner().transaction();
+	for i in 0..200u32 {
+		batch.put(0, &i.to_be_bytes(), b"value");
+	}
+	db.inner().write(batch).unwrap();
+
+	block_on(async {
+		let mut first = db.iter(0);
+		let second = db.iter_with_prefix(0, &[0, 0]);
+		// the streams waiting for their consumer leave the only pool thread available
+		assert!(db.get(0, &0u32.to_be_bytes()).await.unwrap().is_some());
+		assert_eq!(first.next().await.unwrap().0.into_vec(), 0u32.to_be_bytes());
+		let keys: Vec<_> = second.map(|(key, _)| key.into_vec()).collect().await;
+		assert_eq!(keys, (0..200u32).map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>());
+		assert_eq!(first.count().await, 199);
+	});
+}
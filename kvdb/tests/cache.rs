@@ -0,0 +1,48 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `CachedDB` tests.
+
+use kvdb::{CachedDB, IoStatsKind, KeyValueDB};
+use kvdb_memorydb::create;
+use parity_util_mem::MallocSizeOfExt;
+
+#[test]
+fn caches_the_budgeted_columns() {
+	let db = CachedDB::new(create(2)).with_column_budget(0, 16);
+	let mut batch = db.transaction();
+	batch.put(0, b"key1", b"cat");
+	batch.put(0, b"key2", b"dog");
+	batch.put(1, b"key1", b"horse");
+	db.write(batch).unwrap();
+	assert_eq!(db.cached_size(0), 14);
+	assert_eq!(db.cached_size(1), 0);
+	assert!(db.malloc_size_of() > db.inner().malloc_size_of());
+
+	assert_eq!(db.get(0, b"key1").unwrap().unwrap(), b"cat");
+	assert_eq!(db.get(1, b"key1").unwrap().unwrap(), b"horse");
+	// evicts "key2", the least recently used
+	assert!(db.get(0, b"key3").unwrap().is_none());
+	assert_eq!(db.cached_size(0), 11);
+	let stats = db.io_stats(IoStatsKind::SincePrevious);
+	assert_eq!(stats.cache_reads, 1);
+	assert_eq!(stats.cache_read_bytes, 3);
+
+	let mut batch = db.transaction();
+	batch.delete(0, b"key1");
+	batch.put(0, b"key3", b"fish");
+	db.write(batch).unwrap();
+	assert!(db.get(0, b"key1").unwrap().is_none());
+	assert_eq!(db.get(0, b"key3").unwrap().unwrap(), b"fish");
+	let mut batch = db.transaction();
+	batch.delete_prefix(0, b"key");
+	db.write(batch).unwrap();
+	assert_eq!(db.cached_size(0), 0);
+	assert!(db.get(0, b"key3").unwrap().is_none());
+	assert_eq!(db.io_stats(IoStatsKind::SincePrevious).cache_reads, 2);
+}
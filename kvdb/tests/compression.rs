@@ -0,0 +1,70 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `CompressedDB` tests.
+
+use kvdb::{Codec, CompressedDB, IoStatsKind, KeyValueDB};
+use kvdb_memorydb::create;
+
+// The codecs compressing the values, enabled by the features.
+fn codecs() -> Vec<Codec> {
+	vec![
+		#[cfg(feature = "lz4")]
+		Codec::Lz4,
+		#[cfg(feature = "zstd")]
+		Codec::Zstd(3),
+		#[cfg(feature = "snappy")]
+		Codec::Snappy,
+	]
+}
+
+#[test]
+fn compresses_the_configured_columns() {
+	for codec in codecs() {
+		let db = CompressedDB::new(create(2)).with_codec(0, codec);
+		let value = vec![42u8; 1024];
+		let mut batch = db.transaction();
+		batch.put(0, b"key", &value);
+		batch.put(1, b"key", &value);
+		db.write(batch).unwrap();
+
+		assert_eq!(db.get(0, b"key").unwrap().unwrap(), value);
+		assert_eq!(db.iter(0).next().unwrap().1.into_vec(), value);
+		assert_eq!(db.inner().get(1, b"key").unwrap().unwrap(), value);
+		assert!(db.inner().get(0, b"key").unwrap().unwrap().len() < value.len());
+		let stats = db.io_stats(IoStatsKind::SincePrevious);
+		// compressed once on write, decompressed on `get` and `iter`
+		assert_eq!(stats.uncompressed_bytes, 3 * value.len() as u64);
+		assert!(stats.compression_ratio() < 0.5);
+		assert_eq!(db.io_stats(IoStatsKind::SincePrevious).uncompressed_bytes, 0);
+	}
+}
+
+#[test]
+fn rejects_merges_into_compressed_columns() {
+	let db = CompressedDB::new(create(2)).with_codec(0, Codec::None);
+	let mut batch = db.transaction();
+	batch.merge(0, b"key", b"operand");
+	assert!(matches!(db.write(batch), Err(kvdb::Error::NotSupported(_))));
+}
+
+#[test]
+fn skips_values_failing_to_decompress() {
+	let db = CompressedDB::new(create(1)).with_codec(0, Codec::None);
+	let mut batch = db.transaction();
+	batch.put(0, b"key2", b"dog");
+	db.write(batch).unwrap();
+	let mut batch = db.transaction();
+	batch.put(0, b"key1", &[42, 1, 2, 3]);
+	db.inner().write(batch).unwrap();
+
+	assert!(db.get(0, b"key1").is_err());
+	assert_eq!(db.get_by_prefix(0, b"key").unwrap().into_vec(), b"dog");
+	let keys: Vec<_> = db.iter(0).map(|(key, _)| key.into_vec()).collect();
+	assert_eq!(keys, vec![b"key2".to_vec()]);
+}
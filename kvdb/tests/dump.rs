@@ -0,0 +1,33 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Dump tests.
+
+use kvdb::{export, import, Error, KeyValueDB};
+use kvdb_memorydb::create;
+
+#[test]
+fn invalid_dumps_report_the_imported_entries() {
+	let db = create(1);
+	let mut batch = db.transaction();
+	for i in 0..5u8 {
+		batch.put(0, &[i], &vec![i; 1024 * 1024]);
+	}
+	db.write(batch).unwrap();
+	let mut dump = Vec::new();
+	export(&db, 0..1, &mut dump).unwrap();
+
+	let len = dump.len();
+	dump[len - 1] ^= 1;
+	let other = create(1);
+	let err = import(&other, &dump[..]).unwrap_err();
+	// the entries are written by batches of 4 MiB
+	let imported = "the first 4 entries of the dump, up to the key [3] of column 0, were already imported";
+	assert!(matches!(err, Error::Corruption(ref msg) if msg.contains(imported)), "{}", err);
+	assert_eq!(other.iter(0).count(), 4);
+}
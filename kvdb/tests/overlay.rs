@@ -0,0 +1,55 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `OverlayDB` tests.
+
+use kvdb::{KeyValueDB, OverlayDB};
+use kvdb_memorydb::create;
+
+#[test]
+fn stages_writes_until_commit() {
+	let db = OverlayDB::new(create(1));
+	let mut batch = db.transaction();
+	batch.put(0, b"key1", b"cat");
+	batch.put(0, b"key2", b"dog");
+	batch.put(0, b"other", b"horse");
+	db.inner().write(batch).unwrap();
+
+	let mut batch = db.transaction();
+	batch.delete_prefix(0, b"key");
+	batch.put(0, b"key2", b"fish");
+	batch.put(0, b"key3", b"pigeon");
+	batch.delete(0, b"other");
+	db.write(batch).unwrap();
+	assert!(db.is_dirty());
+	assert!(db.get(0, b"key1").unwrap().is_none());
+	assert_eq!(db.get(0, b"key2").unwrap().unwrap(), b"fish");
+	assert!(db.get(0, b"other").unwrap().is_none());
+	assert_eq!(db.inner().get(0, b"key1").unwrap().unwrap(), b"cat");
+	let keys: Vec<_> = db.iter(0).map(|(key, _)| key.into_vec()).collect();
+	assert_eq!(keys, vec![b"key2".to_vec(), b"key3".to_vec()]);
+	assert_eq!(db.get_by_prefix(0, b"key").unwrap().into_vec(), b"fish");
+	assert_eq!(db.pending().ops.len(), 4);
+
+	db.discard();
+	assert!(!db.is_dirty());
+	assert_eq!(db.get(0, b"key1").unwrap().unwrap(), b"cat");
+
+	let mut batch = db.transaction();
+	batch.delete(0, b"key1");
+	batch.put(0, b"key3", b"pigeon");
+	db.write(batch).unwrap();
+	db.commit().unwrap();
+	assert!(!db.is_dirty());
+	assert!(db.inner().get(0, b"key1").unwrap().is_none());
+	assert_eq!(db.inner().get(0, b"key3").unwrap().unwrap(), b"pigeon");
+
+	let mut batch = db.transaction();
+	batch.merge(0, b"key", b"operand");
+	assert!(matches!(db.write(batch), Err(kvdb::Error::NotSupported(_))));
+}
@@ -0,0 +1,67 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `PrefixedDB` tests.
+
+use kvdb::{DBTransaction, IoStatsKind, KeyValueDB, PrefixedDB};
+use kvdb_memorydb::create;
+use std::{ops::Bound, sync::Arc};
+
+type KeyValueIter<'a> = Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+
+#[test]
+fn views_are_isolated() {
+	let db = Arc::new(create(1));
+	let first = PrefixedDB::new(db.clone(), b"first/");
+	let second = PrefixedDB::new(db.clone(), b"second/");
+	let mut batch = DBTransaction::new();
+	batch.put(0, b"key1", b"cat");
+	batch.put(0, b"key2", b"dog");
+	first.write(batch).unwrap();
+	let mut batch = DBTransaction::new();
+	batch.put(0, b"key1", b"horse");
+	second.write(batch).unwrap();
+
+	assert_eq!(db.get(0, b"first/key1").unwrap().unwrap(), b"cat");
+	assert_eq!(first.get(0, b"key1").unwrap().unwrap(), b"cat");
+	assert_eq!(second.get(0, b"key1").unwrap().unwrap(), b"horse");
+	assert!(second.get(0, b"key2").unwrap().is_none());
+	let keys: Vec<_> = first.iter(0).map(|(key, _)| key.into_vec()).collect();
+	assert_eq!(keys, vec![b"key1".to_vec(), b"key2".to_vec()]);
+	assert_eq!(first.iter_with_prefix(0, b"key2").count(), 1);
+
+	let mut batch = DBTransaction::new();
+	batch.delete_prefix(0, b"");
+	first.write(batch).unwrap();
+	assert!(first.iter(0).next().is_none());
+	assert_eq!(second.get(0, b"key1").unwrap().unwrap(), b"horse");
+
+	let stats = first.io_stats(IoStatsKind::Overall);
+	assert_eq!(stats.transactions, 2);
+	assert_eq!(stats.reads, 1);
+	assert_eq!(second.io_stats(IoStatsKind::Overall).transactions, 1);
+}
+
+#[test]
+fn ranges_are_scoped_to_the_view() {
+	let db = Arc::new(create(1));
+	let mut batch = DBTransaction::new();
+	for key in &[&b"a"[..], b"a/1", b"a/2", b"a/3", b"b", b"b/1"] {
+		batch.put(0, key, b"value");
+	}
+	db.write(batch).unwrap();
+	let view = PrefixedDB::new(db, b"a/");
+	let keys = |iter: KeyValueIter<'_>| iter.map(|(key, _)| key.into_vec()).collect::<Vec<_>>();
+
+	assert_eq!(keys(view.iter_range(0, Bound::Unbounded, Bound::Unbounded)), vec![b"1", b"2", b"3"]);
+	assert_eq!(keys(view.iter_range_rev(0, Bound::Unbounded, Bound::Unbounded)), vec![b"3", b"2", b"1"]);
+	assert_eq!(keys(view.iter_range(0, Bound::Excluded(b"1"), Bound::Included(b"2"))), vec![b"2"]);
+	assert_eq!(keys(view.iter_range_rev(0, Bound::Included(b"2"), Bound::Unbounded)), vec![b"3", b"2"]);
+	assert_eq!(keys(view.iter_with_prefix(0, b"3")), vec![b"3"]);
+	assert_eq!(keys(view.snapshot().iter_with_prefix(0, b"2")), vec![b"2"]);
+}
@@ -0,0 +1,40 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `Schema` tests.
+
+use kvdb::{KeyValueDB, Migration, Schema};
+use kvdb_memorydb::create;
+
+#[test]
+fn steps_see_the_data_left_by_the_previous_ones() {
+	let db = create(3);
+	let mut schema = Schema::load(&db, 0).unwrap();
+	schema.migrate(&db, 3, &[Migration::new(1).add_column("a")]).unwrap();
+	let mut batch = db.transaction();
+	batch.put(1, b"key", b"value");
+	db.write(batch).unwrap();
+
+	let append = |_: &[u8], value: &[u8]| Some([value, b"!"].concat());
+	let migration = Migration::new(2)
+		.drop_column("a")
+		.add_column("b")
+		.rewrite_values("b", append)
+		.add_column("c")
+		.rewrite_values("c", append);
+	schema.migrate(&db, 3, &[migration]).unwrap();
+	assert_eq!(schema.column("b"), Some(1));
+	assert!(db.iter(1).next().is_none());
+
+	let mut batch = db.transaction();
+	batch.put(2, b"key", b"value");
+	db.write(batch).unwrap();
+	let migration = Migration::new(3).rewrite_values("c", append).rewrite_values("c", append);
+	schema.migrate(&db, 3, &[migration]).unwrap();
+	assert_eq!(db.get(2, b"key").unwrap().unwrap(), b"value!!");
+}
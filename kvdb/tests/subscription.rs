@@ -0,0 +1,79 @@
+// Copyright 2020 Parity Technologies
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `SubscribableDB` tests.
+
+use kvdb::{DBOp, KeyValueDB, SubscribableDB, SubscriptionFilter};
+use kvdb_memorydb::create;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn notifies_committed_writes() {
+	let db = SubscribableDB::new(create(2));
+	let all = Arc::new(Mutex::new(Vec::new()));
+	let prefixed = Arc::new(Mutex::new(Vec::new()));
+	let all_clone = all.clone();
+	let all_id =
+		db.subscribe(SubscriptionFilter::All, move |ops: &[DBOp]| all_clone.lock().unwrap().extend_from_slice(ops));
+	let prefixed_clone = prefixed.clone();
+	db.subscribe(SubscriptionFilter::Prefix(1, b"ab"[..].into()), move |ops: &[DBOp]| {
+		prefixed_clone.lock().unwrap().push(ops.len())
+	});
+
+	let mut batch = db.transaction();
+	batch.put(0, b"abc", b"cat");
+	batch.put(1, b"abc", b"dog");
+	batch.put(1, b"b", b"fish");
+	db.write(batch).unwrap();
+	assert_eq!(all.lock().unwrap().len(), 3);
+	assert_eq!(*prefixed.lock().unwrap(), vec![1]);
+
+	// failed writes are not notified
+	let mut batch = db.transaction();
+	batch.put(2, b"abc", b"mule");
+	batch.merge(1, b"abc", b"horse");
+	assert!(db.write(batch).is_err());
+	assert_eq!(all.lock().unwrap().len(), 3);
+
+	assert!(db.unsubscribe(all_id));
+	assert!(!db.unsubscribe(all_id));
+	let mut batch = db.transaction();
+	batch.delete_prefix(1, b"a");
+	db.write(batch).unwrap();
+	assert_eq!(all.lock().unwrap().len(), 3);
+	assert_eq!(*prefixed.lock().unwrap(), vec![1, 1]);
+}
+
+#[test]
+fn notifies_in_commit_order() {
+	let db = Arc::new(SubscribableDB::new(create(1)));
+	let last = Arc::new(Mutex::new(None));
+	let last_clone = last.clone();
+	db.subscribe(SubscriptionFilter::All, move |ops: &[DBOp]| {
+		if let Some(DBOp::Insert { value, .. }) = ops.last() {
+			*last_clone.lock().unwrap() = Some(value.clone());
+		}
+	});
+
+	let writers: Vec<_> = (0..4u8)
+		.map(|writer| {
+			let db = db.clone();
+			std::thread::spawn(move || {
+				for i in 0..100u8 {
+					let mut batch = db.transaction();
+					batch.put(0, b"key", &[writer, i]);
+					db.write(batch).unwrap();
+				}
+			})
+		})
+		.collect();
+	for writer in writers {
+		writer.join().unwrap();
+	}
+	assert_eq!(*last.lock().unwrap(), db.get(0, b"key").unwrap());
+}